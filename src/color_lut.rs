@@ -0,0 +1,71 @@
+use crate::colors::{rgb_difference, ColorSearch, ColorTree, Palette, PaletteColor};
+use image::Rgb;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// The number of 24-bit RGB colors.
+const LUT_SIZE: usize = 1 << 24;
+
+/// Marks an entry of the table that hasn't been searched yet.
+const EMPTY: u16 = u16::MAX;
+
+/// A lookup table with the closest palette color for every 24-bit RGB color.
+///
+/// The table is filled lazily: the first lookup of a color asks the `ColorTree` and stores the
+/// palette index, so every later lookup of that color is a single load. The results are exactly
+/// the same as the tree's, for every distance metric. The table takes 32 MiB and can be shared
+/// between threads, which may search the same color at the same time without harm.
+pub struct ColorLut {
+    tree: ColorTree,
+    /// The palette index of the closest color, or `EMPTY`, indexed by `0xRRGGBB`.
+    entries: Vec<AtomicU16>,
+    /// The RGB value of each palette index.
+    colors: [Rgb<u8>; 256],
+}
+
+impl ColorLut {
+    /// Creates an empty lookup table that is filled from the given tree.
+    pub fn new(tree: ColorTree) -> Self {
+        let mut colors = [Rgb([0, 0, 0]); 256];
+        for color in tree.palette().colors() {
+            colors[color.index as usize] = color.rgb;
+        }
+
+        ColorLut {
+            tree,
+            entries: (0..LUT_SIZE).map(|_| AtomicU16::new(EMPTY)).collect(),
+            colors,
+        }
+    }
+
+    /// Returns the tree that fills the table.
+    pub fn tree(&self) -> &ColorTree {
+        &self.tree
+    }
+}
+
+impl ColorSearch for ColorLut {
+    fn palette(&self) -> &Arc<Palette> {
+        self.tree.palette()
+    }
+
+    fn find_closest(&self, color: &Rgb<u8>) -> (PaletteColor, [i16; 3]) {
+        let [r, g, b] = color.0;
+        let entry = &self.entries[(r as usize) << 16 | (g as usize) << 8 | b as usize];
+
+        // Relaxed is enough, the table only ever changes from EMPTY to the one correct index
+        let nearest = match entry.load(Ordering::Relaxed) {
+            EMPTY => {
+                let (nearest, _) = self.tree.find_closest(color);
+                entry.store(nearest.index as u16, Ordering::Relaxed);
+                nearest
+            }
+            index => PaletteColor {
+                index: index as u8,
+                rgb: self.colors[index as usize],
+            },
+        };
+
+        (nearest, rgb_difference(color, &nearest.rgb))
+    }
+}
@@ -0,0 +1,151 @@
+use image::Rgb;
+use std::sync::OnceLock;
+
+/// The D65 white point in XYZ, used as the reference white of CIELAB.
+const D65: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+static SRGB_TO_LINEAR: OnceLock<[f32; 256]> = OnceLock::new();
+
+/// Decodes an sRGB channel to linear light in the range [0, 1].
+pub fn srgb_to_linear(channel: u8) -> f32 {
+    SRGB_TO_LINEAR.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *value = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    })[channel as usize]
+}
+
+/// Encodes a linear light value in the range [0, 1] to the closest sRGB channel value.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+fn linear_rgb(color: &Rgb<u8>) -> [f32; 3] {
+    color.0.map(srgb_to_linear)
+}
+
+/// Converts an sRGB color to CIELAB with a D65 white point.
+#[allow(clippy::excessive_precision)]
+pub fn to_lab(color: &Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = linear_rgb(color);
+    let xyz = [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+    ];
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / D65[i]));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Converts an sRGB color to OKLab. The matrices are from the reference implementation.
+#[allow(clippy::excessive_precision)]
+pub fn to_oklab(color: &Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = linear_rgb(color);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// Returns the squared "redmean" distance, a weighted RGB distance that approximates perception.
+pub fn redmean_squared(a: &Rgb<u8>, b: &Rgb<u8>) -> f32 {
+    let mean_red = (a[0] as f32 + b[0] as f32) / 2.0;
+    let [dr, dg, db] = [0, 1, 2].map(|i| a[i] as f32 - b[i] as f32);
+    (2.0 + mean_red / 256.0) * dr * dr
+        + 4.0 * dg * dg
+        + (2.0 + (255.0 - mean_red) / 256.0) * db * db
+}
+
+/// Returns the CIEDE2000 color difference between two CIELAB colors.
+pub fn ciede2000(lab1: [f32; 3], lab2: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let mean_c7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (mean_c7 / (mean_c7 + 25.0_f32.powi(7))).sqrt());
+
+    let a1 = a1 * (1.0 + g);
+    let a2 = a2 * (1.0 + g);
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let hue = |a: f32, b: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1 = hue(a1, b1);
+    let h2 = hue(a2, b2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let mean_l = (l1 + l2) / 2.0;
+    let mean_c = (c1 + c2) / 2.0;
+    let mean_h = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (mean_h - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * mean_h).to_radians().cos()
+        + 0.32 * (3.0 * mean_h + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * mean_h - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((mean_h - 275.0) / 25.0).powi(2)).exp();
+    let mean_c7 = mean_c.powi(7);
+    let r_c = 2.0 * (mean_c7 / (mean_c7 + 25.0_f32.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (mean_l - 50.0).powi(2) / (20.0 + (mean_l - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * mean_c;
+    let s_h = 1.0 + 0.015 * mean_c * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let l = delta_l / s_l;
+    let c = delta_c / s_c;
+    let h = delta_h / s_h;
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
@@ -1,299 +1,830 @@
+use crate::color_space::{ciede2000, redmean_squared, to_lab, to_oklab};
 use image::Rgb;
 use kd_tree::KdTree3;
-use std::sync::OnceLock;
+use std::sync::Arc;
 use typenum::U3;
 
 /// The number of colors that Minecraft supports, excluding the 4 transparent ones.
 const COLOR_COUNT: usize = 244;
 
-/// A wrapper around `Rgb<u8>` that implements the `KdPoint` trait for use with the `kd-tree`.
-/// Contains the original `Rgb<u8>` value and the index of that color in the Minecraft color list.
+/// The map color ID of the first entry in `COLOR_LIST`. IDs 0 to 3 are the transparent colors.
+const FIRST_COLOR_ID: u8 = 4;
+
+/// A wrapper around `PaletteColor` that implements the `KdPoint` trait for use with the `kd-tree`.
+/// Contains the original `Rgb<u8>` value and the index of that color in the palette,
+/// along with the coordinates of the color in the color space of the `DistanceMetric`.
 #[derive(Debug, Clone, Copy)]
-pub struct SearchableRgb(Rgb<u8>);
+pub struct SearchableRgb(PaletteColor, [f32; 3]);
 impl kd_tree::KdPoint for SearchableRgb {
-    type Scalar = isize;
+    type Scalar = f32;
     type Dim = U3;
 
     fn at(&self, k: usize) -> Self::Scalar {
-        self.0[k] as isize
+        self.1[k]
     }
 }
 
 /// This array contains `COLOR_COUNT` entries
 /// Each element in this array represents a Minecraft map color
 /// As of Minecraft 1.21, there are 244 colours in this array.
+/// The colors are grouped by base color, which always have four shades.
+/// Older versions only support a prefix of this array, see `MinecraftVersion`.
 #[allow(overflowing_literals)]
-const COLOR_LIST: [SearchableRgb; COLOR_COUNT] = [
-    SearchableRgb(Rgb([89, 125, 39])),
-    SearchableRgb(Rgb([109, 153, 48])),
-    SearchableRgb(Rgb([127, 178, 56])),
-    SearchableRgb(Rgb([67, 94, 29])),
-    SearchableRgb(Rgb([174, 164, 115])),
-    SearchableRgb(Rgb([213, 201, 140])),
-    SearchableRgb(Rgb([247, 233, 163])),
-    SearchableRgb(Rgb([130, 123, 86])),
-    SearchableRgb(Rgb([140, 140, 140])),
-    SearchableRgb(Rgb([171, 171, 171])),
-    SearchableRgb(Rgb([199, 199, 199])),
-    SearchableRgb(Rgb([105, 105, 105])),
-    SearchableRgb(Rgb([180, 0, 0])),
-    SearchableRgb(Rgb([220, 0, 0])),
-    SearchableRgb(Rgb([255, 0, 0])),
-    SearchableRgb(Rgb([135, 0, 0])),
-    SearchableRgb(Rgb([112, 112, 180])),
-    SearchableRgb(Rgb([138, 138, 220])),
-    SearchableRgb(Rgb([160, 160, 255])),
-    SearchableRgb(Rgb([84, 84, 135])),
-    SearchableRgb(Rgb([117, 117, 117])),
-    SearchableRgb(Rgb([144, 144, 144])),
-    SearchableRgb(Rgb([167, 167, 167])),
-    SearchableRgb(Rgb([88, 88, 88])),
-    SearchableRgb(Rgb([0, 87, 0])),
-    SearchableRgb(Rgb([0, 106, 0])),
-    SearchableRgb(Rgb([0, 124, 0])),
-    SearchableRgb(Rgb([0, 65, 0])),
-    SearchableRgb(Rgb([180, 180, 180])),
-    SearchableRgb(Rgb([220, 220, 220])),
-    SearchableRgb(Rgb([255, 255, 255])),
-    SearchableRgb(Rgb([135, 135, 135])),
-    SearchableRgb(Rgb([115, 118, 129])),
-    SearchableRgb(Rgb([141, 144, 158])),
-    SearchableRgb(Rgb([164, 168, 184])),
-    SearchableRgb(Rgb([86, 88, 97])),
-    SearchableRgb(Rgb([106, 76, 54])),
-    SearchableRgb(Rgb([130, 94, 66])),
-    SearchableRgb(Rgb([151, 109, 77])),
-    SearchableRgb(Rgb([79, 57, 40])),
-    SearchableRgb(Rgb([79, 79, 79])),
-    SearchableRgb(Rgb([96, 96, 96])),
-    SearchableRgb(Rgb([112, 112, 112])),
-    SearchableRgb(Rgb([59, 59, 59])),
-    SearchableRgb(Rgb([45, 45, 180])),
-    SearchableRgb(Rgb([55, 55, 220])),
-    SearchableRgb(Rgb([64, 64, 255])),
-    SearchableRgb(Rgb([33, 33, 135])),
-    SearchableRgb(Rgb([100, 84, 50])),
-    SearchableRgb(Rgb([123, 102, 62])),
-    SearchableRgb(Rgb([143, 119, 72])),
-    SearchableRgb(Rgb([75, 63, 38])),
-    SearchableRgb(Rgb([180, 177, 172])),
-    SearchableRgb(Rgb([220, 217, 211])),
-    SearchableRgb(Rgb([255, 252, 245])),
-    SearchableRgb(Rgb([135, 133, 129])),
-    SearchableRgb(Rgb([152, 89, 36])),
-    SearchableRgb(Rgb([186, 109, 44])),
-    SearchableRgb(Rgb([216, 127, 51])),
-    SearchableRgb(Rgb([114, 67, 27])),
-    SearchableRgb(Rgb([125, 53, 152])),
-    SearchableRgb(Rgb([153, 65, 186])),
-    SearchableRgb(Rgb([178, 76, 216])),
-    SearchableRgb(Rgb([94, 40, 114])),
-    SearchableRgb(Rgb([72, 108, 152])),
-    SearchableRgb(Rgb([88, 132, 186])),
-    SearchableRgb(Rgb([102, 153, 216])),
-    SearchableRgb(Rgb([54, 81, 114])),
-    SearchableRgb(Rgb([161, 161, 36])),
-    SearchableRgb(Rgb([197, 197, 44])),
-    SearchableRgb(Rgb([229, 229, 51])),
-    SearchableRgb(Rgb([121, 121, 27])),
-    SearchableRgb(Rgb([89, 144, 17])),
-    SearchableRgb(Rgb([109, 176, 21])),
-    SearchableRgb(Rgb([127, 204, 25])),
-    SearchableRgb(Rgb([67, 108, 13])),
-    SearchableRgb(Rgb([170, 89, 116])),
-    SearchableRgb(Rgb([208, 109, 142])),
-    SearchableRgb(Rgb([242, 127, 165])),
-    SearchableRgb(Rgb([128, 67, 87])),
-    SearchableRgb(Rgb([53, 53, 53])),
-    SearchableRgb(Rgb([65, 65, 65])),
-    SearchableRgb(Rgb([76, 76, 76])),
-    SearchableRgb(Rgb([40, 40, 40])),
-    SearchableRgb(Rgb([108, 108, 108])),
-    SearchableRgb(Rgb([132, 132, 132])),
-    SearchableRgb(Rgb([153, 153, 153])),
-    SearchableRgb(Rgb([81, 81, 81])),
-    SearchableRgb(Rgb([53, 89, 108])),
-    SearchableRgb(Rgb([65, 109, 132])),
-    SearchableRgb(Rgb([76, 127, 153])),
-    SearchableRgb(Rgb([40, 67, 81])),
-    SearchableRgb(Rgb([89, 44, 125])),
-    SearchableRgb(Rgb([109, 54, 153])),
-    SearchableRgb(Rgb([127, 63, 178])),
-    SearchableRgb(Rgb([67, 33, 94])),
-    SearchableRgb(Rgb([36, 53, 125])),
-    SearchableRgb(Rgb([44, 65, 153])),
-    SearchableRgb(Rgb([51, 76, 178])),
-    SearchableRgb(Rgb([27, 40, 94])),
-    SearchableRgb(Rgb([72, 53, 36])),
-    SearchableRgb(Rgb([88, 65, 44])),
-    SearchableRgb(Rgb([102, 76, 51])),
-    SearchableRgb(Rgb([54, 40, 27])),
-    SearchableRgb(Rgb([72, 89, 36])),
-    SearchableRgb(Rgb([88, 109, 44])),
-    SearchableRgb(Rgb([102, 127, 51])),
-    SearchableRgb(Rgb([54, 67, 27])),
-    SearchableRgb(Rgb([108, 36, 36])),
-    SearchableRgb(Rgb([132, 44, 44])),
-    SearchableRgb(Rgb([153, 51, 51])),
-    SearchableRgb(Rgb([81, 27, 27])),
-    SearchableRgb(Rgb([17, 17, 17])),
-    SearchableRgb(Rgb([21, 21, 21])),
-    SearchableRgb(Rgb([25, 25, 25])),
-    SearchableRgb(Rgb([13, 13, 13])),
-    SearchableRgb(Rgb([176, 168, 54])),
-    SearchableRgb(Rgb([215, 205, 66])),
-    SearchableRgb(Rgb([250, 238, 77])),
-    SearchableRgb(Rgb([132, 126, 40])),
-    SearchableRgb(Rgb([64, 154, 150])),
-    SearchableRgb(Rgb([79, 188, 183])),
-    SearchableRgb(Rgb([92, 219, 213])),
-    SearchableRgb(Rgb([48, 115, 112])),
-    SearchableRgb(Rgb([52, 90, 180])),
-    SearchableRgb(Rgb([63, 110, 220])),
-    SearchableRgb(Rgb([74, 128, 255])),
-    SearchableRgb(Rgb([39, 67, 135])),
-    SearchableRgb(Rgb([0, 153, 40])),
-    SearchableRgb(Rgb([0, 187, 50])),
-    SearchableRgb(Rgb([0, 217, 58])),
-    SearchableRgb(Rgb([0, 114, 30])),
-    SearchableRgb(Rgb([91, 60, 34])),
-    SearchableRgb(Rgb([111, 74, 42])),
-    SearchableRgb(Rgb([129, 86, 49])),
-    SearchableRgb(Rgb([68, 45, 25])),
-    SearchableRgb(Rgb([79, 1, 0])),
-    SearchableRgb(Rgb([96, 1, 0])),
-    SearchableRgb(Rgb([112, 2, 0])),
-    SearchableRgb(Rgb([59, 1, 0])),
-    SearchableRgb(Rgb([147, 124, 113])),
-    SearchableRgb(Rgb([180, 152, 138])),
-    SearchableRgb(Rgb([209, 177, 161])),
-    SearchableRgb(Rgb([110, 93, 85])),
-    SearchableRgb(Rgb([112, 57, 25])),
-    SearchableRgb(Rgb([137, 70, 31])),
-    SearchableRgb(Rgb([159, 82, 36])),
-    SearchableRgb(Rgb([84, 43, 19])),
-    SearchableRgb(Rgb([105, 61, 76])),
-    SearchableRgb(Rgb([128, 75, 93])),
-    SearchableRgb(Rgb([149, 87, 108])),
-    SearchableRgb(Rgb([78, 46, 57])),
-    SearchableRgb(Rgb([79, 76, 97])),
-    SearchableRgb(Rgb([96, 93, 119])),
-    SearchableRgb(Rgb([112, 108, 138])),
-    SearchableRgb(Rgb([59, 57, 73])),
-    SearchableRgb(Rgb([131, 93, 25])),
-    SearchableRgb(Rgb([160, 114, 31])),
-    SearchableRgb(Rgb([186, 133, 36])),
-    SearchableRgb(Rgb([98, 70, 19])),
-    SearchableRgb(Rgb([72, 82, 37])),
-    SearchableRgb(Rgb([88, 100, 45])),
-    SearchableRgb(Rgb([103, 117, 53])),
-    SearchableRgb(Rgb([54, 61, 28])),
-    SearchableRgb(Rgb([112, 54, 55])),
-    SearchableRgb(Rgb([138, 66, 67])),
-    SearchableRgb(Rgb([160, 77, 78])),
-    SearchableRgb(Rgb([84, 40, 41])),
-    SearchableRgb(Rgb([40, 28, 24])),
-    SearchableRgb(Rgb([49, 35, 30])),
-    SearchableRgb(Rgb([57, 41, 35])),
-    SearchableRgb(Rgb([30, 21, 18])),
-    SearchableRgb(Rgb([95, 75, 69])),
-    SearchableRgb(Rgb([116, 92, 84])),
-    SearchableRgb(Rgb([135, 107, 98])),
-    SearchableRgb(Rgb([71, 56, 51])),
-    SearchableRgb(Rgb([61, 64, 64])),
-    SearchableRgb(Rgb([75, 79, 79])),
-    SearchableRgb(Rgb([87, 92, 92])),
-    SearchableRgb(Rgb([46, 48, 48])),
-    SearchableRgb(Rgb([86, 51, 62])),
-    SearchableRgb(Rgb([105, 62, 75])),
-    SearchableRgb(Rgb([122, 73, 88])),
-    SearchableRgb(Rgb([64, 38, 46])),
-    SearchableRgb(Rgb([53, 43, 64])),
-    SearchableRgb(Rgb([65, 53, 79])),
-    SearchableRgb(Rgb([76, 62, 92])),
-    SearchableRgb(Rgb([40, 32, 48])),
-    SearchableRgb(Rgb([53, 35, 24])),
-    SearchableRgb(Rgb([65, 43, 30])),
-    SearchableRgb(Rgb([76, 50, 35])),
-    SearchableRgb(Rgb([40, 26, 18])),
-    SearchableRgb(Rgb([53, 57, 29])),
-    SearchableRgb(Rgb([65, 70, 36])),
-    SearchableRgb(Rgb([76, 82, 42])),
-    SearchableRgb(Rgb([40, 43, 22])),
-    SearchableRgb(Rgb([100, 42, 32])),
-    SearchableRgb(Rgb([122, 51, 39])),
-    SearchableRgb(Rgb([142, 60, 46])),
-    SearchableRgb(Rgb([75, 31, 24])),
-    SearchableRgb(Rgb([26, 15, 11])),
-    SearchableRgb(Rgb([31, 18, 13])),
-    SearchableRgb(Rgb([37, 22, 16])),
-    SearchableRgb(Rgb([19, 11, 8])),
-    SearchableRgb(Rgb([133, 33, 34])),
-    SearchableRgb(Rgb([163, 41, 42])),
-    SearchableRgb(Rgb([189, 48, 49])),
-    SearchableRgb(Rgb([100, 25, 25])),
-    SearchableRgb(Rgb([104, 44, 68])),
-    SearchableRgb(Rgb([127, 54, 83])),
-    SearchableRgb(Rgb([148, 63, 97])),
-    SearchableRgb(Rgb([78, 33, 51])),
-    SearchableRgb(Rgb([64, 17, 20])),
-    SearchableRgb(Rgb([79, 21, 25])),
-    SearchableRgb(Rgb([92, 25, 29])),
-    SearchableRgb(Rgb([48, 13, 15])),
-    SearchableRgb(Rgb([15, 88, 94])),
-    SearchableRgb(Rgb([18, 108, 115])),
-    SearchableRgb(Rgb([22, 126, 134])),
-    SearchableRgb(Rgb([11, 66, 70])),
-    SearchableRgb(Rgb([40, 100, 98])),
-    SearchableRgb(Rgb([50, 122, 120])),
-    SearchableRgb(Rgb([58, 142, 140])),
-    SearchableRgb(Rgb([30, 75, 74])),
-    SearchableRgb(Rgb([60, 31, 43])),
-    SearchableRgb(Rgb([74, 37, 53])),
-    SearchableRgb(Rgb([86, 44, 62])),
-    SearchableRgb(Rgb([45, 23, 32])),
-    SearchableRgb(Rgb([14, 127, 93])),
-    SearchableRgb(Rgb([17, 155, 114])),
-    SearchableRgb(Rgb([20, 180, 133])),
-    SearchableRgb(Rgb([10, 95, 70])),
-    SearchableRgb(Rgb([70, 70, 70])),
-    SearchableRgb(Rgb([86, 86, 86])),
-    SearchableRgb(Rgb([100, 100, 100])),
-    SearchableRgb(Rgb([52, 52, 52])),
-    SearchableRgb(Rgb([152, 123, 103])),
-    SearchableRgb(Rgb([186, 150, 126])),
-    SearchableRgb(Rgb([216, 175, 147])),
-    SearchableRgb(Rgb([114, 92, 77])),
-    SearchableRgb(Rgb([89, 117, 105])),
-    SearchableRgb(Rgb([109, 144, 129])),
-    SearchableRgb(Rgb([127, 167, 150])),
-    SearchableRgb(Rgb([67, 88, 79])),
+const COLOR_LIST: [Rgb<u8>; COLOR_COUNT] = [
+    Rgb([89, 125, 39]),
+    Rgb([109, 153, 48]),
+    Rgb([127, 178, 56]),
+    Rgb([67, 94, 29]),
+    Rgb([174, 164, 115]),
+    Rgb([213, 201, 140]),
+    Rgb([247, 233, 163]),
+    Rgb([130, 123, 86]),
+    Rgb([140, 140, 140]),
+    Rgb([171, 171, 171]),
+    Rgb([199, 199, 199]),
+    Rgb([105, 105, 105]),
+    Rgb([180, 0, 0]),
+    Rgb([220, 0, 0]),
+    Rgb([255, 0, 0]),
+    Rgb([135, 0, 0]),
+    Rgb([112, 112, 180]),
+    Rgb([138, 138, 220]),
+    Rgb([160, 160, 255]),
+    Rgb([84, 84, 135]),
+    Rgb([117, 117, 117]),
+    Rgb([144, 144, 144]),
+    Rgb([167, 167, 167]),
+    Rgb([88, 88, 88]),
+    Rgb([0, 87, 0]),
+    Rgb([0, 106, 0]),
+    Rgb([0, 124, 0]),
+    Rgb([0, 65, 0]),
+    Rgb([180, 180, 180]),
+    Rgb([220, 220, 220]),
+    Rgb([255, 255, 255]),
+    Rgb([135, 135, 135]),
+    Rgb([115, 118, 129]),
+    Rgb([141, 144, 158]),
+    Rgb([164, 168, 184]),
+    Rgb([86, 88, 97]),
+    Rgb([106, 76, 54]),
+    Rgb([130, 94, 66]),
+    Rgb([151, 109, 77]),
+    Rgb([79, 57, 40]),
+    Rgb([79, 79, 79]),
+    Rgb([96, 96, 96]),
+    Rgb([112, 112, 112]),
+    Rgb([59, 59, 59]),
+    Rgb([45, 45, 180]),
+    Rgb([55, 55, 220]),
+    Rgb([64, 64, 255]),
+    Rgb([33, 33, 135]),
+    Rgb([100, 84, 50]),
+    Rgb([123, 102, 62]),
+    Rgb([143, 119, 72]),
+    Rgb([75, 63, 38]),
+    Rgb([180, 177, 172]),
+    Rgb([220, 217, 211]),
+    Rgb([255, 252, 245]),
+    Rgb([135, 133, 129]),
+    Rgb([152, 89, 36]),
+    Rgb([186, 109, 44]),
+    Rgb([216, 127, 51]),
+    Rgb([114, 67, 27]),
+    Rgb([125, 53, 152]),
+    Rgb([153, 65, 186]),
+    Rgb([178, 76, 216]),
+    Rgb([94, 40, 114]),
+    Rgb([72, 108, 152]),
+    Rgb([88, 132, 186]),
+    Rgb([102, 153, 216]),
+    Rgb([54, 81, 114]),
+    Rgb([161, 161, 36]),
+    Rgb([197, 197, 44]),
+    Rgb([229, 229, 51]),
+    Rgb([121, 121, 27]),
+    Rgb([89, 144, 17]),
+    Rgb([109, 176, 21]),
+    Rgb([127, 204, 25]),
+    Rgb([67, 108, 13]),
+    Rgb([170, 89, 116]),
+    Rgb([208, 109, 142]),
+    Rgb([242, 127, 165]),
+    Rgb([128, 67, 87]),
+    Rgb([53, 53, 53]),
+    Rgb([65, 65, 65]),
+    Rgb([76, 76, 76]),
+    Rgb([40, 40, 40]),
+    Rgb([108, 108, 108]),
+    Rgb([132, 132, 132]),
+    Rgb([153, 153, 153]),
+    Rgb([81, 81, 81]),
+    Rgb([53, 89, 108]),
+    Rgb([65, 109, 132]),
+    Rgb([76, 127, 153]),
+    Rgb([40, 67, 81]),
+    Rgb([89, 44, 125]),
+    Rgb([109, 54, 153]),
+    Rgb([127, 63, 178]),
+    Rgb([67, 33, 94]),
+    Rgb([36, 53, 125]),
+    Rgb([44, 65, 153]),
+    Rgb([51, 76, 178]),
+    Rgb([27, 40, 94]),
+    Rgb([72, 53, 36]),
+    Rgb([88, 65, 44]),
+    Rgb([102, 76, 51]),
+    Rgb([54, 40, 27]),
+    Rgb([72, 89, 36]),
+    Rgb([88, 109, 44]),
+    Rgb([102, 127, 51]),
+    Rgb([54, 67, 27]),
+    Rgb([108, 36, 36]),
+    Rgb([132, 44, 44]),
+    Rgb([153, 51, 51]),
+    Rgb([81, 27, 27]),
+    Rgb([17, 17, 17]),
+    Rgb([21, 21, 21]),
+    Rgb([25, 25, 25]),
+    Rgb([13, 13, 13]),
+    Rgb([176, 168, 54]),
+    Rgb([215, 205, 66]),
+    Rgb([250, 238, 77]),
+    Rgb([132, 126, 40]),
+    Rgb([64, 154, 150]),
+    Rgb([79, 188, 183]),
+    Rgb([92, 219, 213]),
+    Rgb([48, 115, 112]),
+    Rgb([52, 90, 180]),
+    Rgb([63, 110, 220]),
+    Rgb([74, 128, 255]),
+    Rgb([39, 67, 135]),
+    Rgb([0, 153, 40]),
+    Rgb([0, 187, 50]),
+    Rgb([0, 217, 58]),
+    Rgb([0, 114, 30]),
+    Rgb([91, 60, 34]),
+    Rgb([111, 74, 42]),
+    Rgb([129, 86, 49]),
+    Rgb([68, 45, 25]),
+    Rgb([79, 1, 0]),
+    Rgb([96, 1, 0]),
+    Rgb([112, 2, 0]),
+    Rgb([59, 1, 0]),
+    Rgb([147, 124, 113]),
+    Rgb([180, 152, 138]),
+    Rgb([209, 177, 161]),
+    Rgb([110, 93, 85]),
+    Rgb([112, 57, 25]),
+    Rgb([137, 70, 31]),
+    Rgb([159, 82, 36]),
+    Rgb([84, 43, 19]),
+    Rgb([105, 61, 76]),
+    Rgb([128, 75, 93]),
+    Rgb([149, 87, 108]),
+    Rgb([78, 46, 57]),
+    Rgb([79, 76, 97]),
+    Rgb([96, 93, 119]),
+    Rgb([112, 108, 138]),
+    Rgb([59, 57, 73]),
+    Rgb([131, 93, 25]),
+    Rgb([160, 114, 31]),
+    Rgb([186, 133, 36]),
+    Rgb([98, 70, 19]),
+    Rgb([72, 82, 37]),
+    Rgb([88, 100, 45]),
+    Rgb([103, 117, 53]),
+    Rgb([54, 61, 28]),
+    Rgb([112, 54, 55]),
+    Rgb([138, 66, 67]),
+    Rgb([160, 77, 78]),
+    Rgb([84, 40, 41]),
+    Rgb([40, 28, 24]),
+    Rgb([49, 35, 30]),
+    Rgb([57, 41, 35]),
+    Rgb([30, 21, 18]),
+    Rgb([95, 75, 69]),
+    Rgb([116, 92, 84]),
+    Rgb([135, 107, 98]),
+    Rgb([71, 56, 51]),
+    Rgb([61, 64, 64]),
+    Rgb([75, 79, 79]),
+    Rgb([87, 92, 92]),
+    Rgb([46, 48, 48]),
+    Rgb([86, 51, 62]),
+    Rgb([105, 62, 75]),
+    Rgb([122, 73, 88]),
+    Rgb([64, 38, 46]),
+    Rgb([53, 43, 64]),
+    Rgb([65, 53, 79]),
+    Rgb([76, 62, 92]),
+    Rgb([40, 32, 48]),
+    Rgb([53, 35, 24]),
+    Rgb([65, 43, 30]),
+    Rgb([76, 50, 35]),
+    Rgb([40, 26, 18]),
+    Rgb([53, 57, 29]),
+    Rgb([65, 70, 36]),
+    Rgb([76, 82, 42]),
+    Rgb([40, 43, 22]),
+    Rgb([100, 42, 32]),
+    Rgb([122, 51, 39]),
+    Rgb([142, 60, 46]),
+    Rgb([75, 31, 24]),
+    Rgb([26, 15, 11]),
+    Rgb([31, 18, 13]),
+    Rgb([37, 22, 16]),
+    Rgb([19, 11, 8]),
+    Rgb([133, 33, 34]),
+    Rgb([163, 41, 42]),
+    Rgb([189, 48, 49]),
+    Rgb([100, 25, 25]),
+    Rgb([104, 44, 68]),
+    Rgb([127, 54, 83]),
+    Rgb([148, 63, 97]),
+    Rgb([78, 33, 51]),
+    Rgb([64, 17, 20]),
+    Rgb([79, 21, 25]),
+    Rgb([92, 25, 29]),
+    Rgb([48, 13, 15]),
+    Rgb([15, 88, 94]),
+    Rgb([18, 108, 115]),
+    Rgb([22, 126, 134]),
+    Rgb([11, 66, 70]),
+    Rgb([40, 100, 98]),
+    Rgb([50, 122, 120]),
+    Rgb([58, 142, 140]),
+    Rgb([30, 75, 74]),
+    Rgb([60, 31, 43]),
+    Rgb([74, 37, 53]),
+    Rgb([86, 44, 62]),
+    Rgb([45, 23, 32]),
+    Rgb([14, 127, 93]),
+    Rgb([17, 155, 114]),
+    Rgb([20, 180, 133]),
+    Rgb([10, 95, 70]),
+    Rgb([70, 70, 70]),
+    Rgb([86, 86, 86]),
+    Rgb([100, 100, 100]),
+    Rgb([52, 52, 52]),
+    Rgb([152, 123, 103]),
+    Rgb([186, 150, 126]),
+    Rgb([216, 175, 147]),
+    Rgb([114, 92, 77]),
+    Rgb([89, 117, 105]),
+    Rgb([109, 144, 129]),
+    Rgb([127, 167, 150]),
+    Rgb([67, 88, 79]),
+];
+
+/// A block that shows up as a certain base color on maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapBlock {
+    /// The block ID without the `minecraft:` namespace.
+    pub id: &'static str,
+    /// Whether the block falls when it isn't supported, like sand and gravel.
+    pub gravity: bool,
+}
+
+const fn solid(id: &'static str) -> MapBlock {
+    MapBlock { id, gravity: false }
+}
+
+const fn falling(id: &'static str) -> MapBlock {
+    MapBlock { id, gravity: true }
+}
+
+/// A base color of the map palette and the blocks that produce it.
+#[derive(Debug, Clone, Copy)]
+pub struct BaseColor {
+    /// The base color ID, so the map color IDs of its shades are `id * 4` to `id * 4 + 3`.
+    pub id: u8,
+    pub name: &'static str,
+    /// A selection of common blocks with this color, not all of them.
+    pub blocks: &'static [MapBlock],
+}
+
+/// The base colors in the same order as `COLOR_LIST`, i.e. `BASE_COLORS[i]` has the colors
+/// `COLOR_LIST[i * 4..i * 4 + 4]`.
+#[rustfmt::skip]
+pub const BASE_COLORS: [BaseColor; COLOR_COUNT / 4] = [
+    BaseColor { id: 1, name: "grass", blocks: &[solid("grass_block"), solid("slime_block")] },
+    BaseColor { id: 2, name: "sand", blocks: &[
+        solid("sandstone"), solid("birch_planks"), solid("glowstone"), solid("end_stone"),
+        solid("bone_block"), falling("sand"),
+    ] },
+    BaseColor { id: 3, name: "wool", blocks: &[solid("mushroom_stem"), solid("cobweb")] },
+    BaseColor { id: 4, name: "fire", blocks: &[
+        solid("redstone_block"), solid("tnt"), solid("lava"),
+    ] },
+    BaseColor { id: 5, name: "ice", blocks: &[
+        solid("packed_ice"), solid("ice"), solid("blue_ice"),
+    ] },
+    BaseColor { id: 6, name: "metal", blocks: &[
+        solid("iron_block"), solid("iron_trapdoor"), solid("heavy_weighted_pressure_plate"),
+        falling("anvil"),
+    ] },
+    BaseColor { id: 7, name: "plant", blocks: &[
+        solid("oak_leaves"), solid("birch_leaves"), solid("spruce_leaves"), solid("jungle_leaves"),
+        solid("acacia_leaves"), solid("dark_oak_leaves"), solid("azalea_leaves"),
+    ] },
+    BaseColor { id: 8, name: "snow", blocks: &[
+        solid("white_concrete"), solid("white_wool"), solid("snow_block"), solid("white_carpet"),
+        falling("white_concrete_powder"),
+    ] },
+    BaseColor { id: 9, name: "clay", blocks: &[solid("clay")] },
+    BaseColor { id: 10, name: "dirt", blocks: &[
+        solid("dirt"), solid("coarse_dirt"), solid("granite"), solid("jungle_planks"),
+        solid("rooted_dirt"), solid("brown_mushroom_block"),
+    ] },
+    BaseColor { id: 11, name: "stone", blocks: &[
+        solid("stone"), solid("cobblestone"), solid("andesite"), solid("stone_bricks"),
+        falling("gravel"),
+    ] },
+    BaseColor { id: 12, name: "water", blocks: &[
+        solid("water"), solid("kelp"), solid("seagrass"),
+    ] },
+    BaseColor { id: 13, name: "wood", blocks: &[
+        solid("oak_planks"), solid("crafting_table"), solid("note_block"), solid("bookshelf"),
+    ] },
+    BaseColor { id: 14, name: "quartz", blocks: &[
+        solid("quartz_block"), solid("diorite"), solid("sea_lantern"), solid("target"),
+    ] },
+    BaseColor { id: 15, name: "color_orange", blocks: &[
+        solid("orange_concrete"), solid("orange_wool"), solid("terracotta"), solid("acacia_planks"),
+        solid("pumpkin"), solid("honeycomb_block"), solid("raw_copper_block"), falling("red_sand"),
+    ] },
+    BaseColor { id: 16, name: "color_magenta", blocks: &[
+        solid("magenta_concrete"), solid("magenta_wool"), solid("purpur_block"),
+    ] },
+    BaseColor { id: 17, name: "color_light_blue", blocks: &[
+        solid("light_blue_concrete"), solid("light_blue_wool"),
+    ] },
+    BaseColor { id: 18, name: "color_yellow", blocks: &[
+        solid("yellow_concrete"), solid("yellow_wool"), solid("hay_block"), solid("sponge"),
+    ] },
+    BaseColor { id: 19, name: "color_light_green", blocks: &[
+        solid("lime_concrete"), solid("lime_wool"), solid("melon"),
+    ] },
+    BaseColor { id: 20, name: "color_pink", blocks: &[
+        solid("pink_concrete"), solid("pink_wool"), solid("brain_coral_block"),
+        solid("pearlescent_froglight"),
+    ] },
+    BaseColor { id: 21, name: "color_gray", blocks: &[
+        solid("gray_concrete"), solid("gray_wool"), solid("tinted_glass"),
+    ] },
+    BaseColor { id: 22, name: "color_light_gray", blocks: &[
+        solid("light_gray_concrete"), solid("light_gray_wool"),
+    ] },
+    BaseColor { id: 23, name: "color_cyan", blocks: &[
+        solid("cyan_concrete"), solid("cyan_wool"), solid("prismarine"),
+    ] },
+    BaseColor { id: 24, name: "color_purple", blocks: &[
+        solid("purple_concrete"), solid("purple_wool"), solid("mycelium"), solid("amethyst_block"),
+    ] },
+    BaseColor { id: 25, name: "color_blue", blocks: &[
+        solid("blue_concrete"), solid("blue_wool"), solid("tube_coral_block"),
+    ] },
+    BaseColor { id: 26, name: "color_brown", blocks: &[
+        solid("brown_concrete"), solid("brown_wool"), solid("dark_oak_planks"), solid("soul_sand"),
+        solid("soul_soil"),
+    ] },
+    BaseColor { id: 27, name: "color_green", blocks: &[
+        solid("green_concrete"), solid("green_wool"), solid("dried_kelp_block"),
+        solid("moss_block"),
+    ] },
+    BaseColor { id: 28, name: "color_red", blocks: &[
+        solid("red_concrete"), solid("red_wool"), solid("bricks"), solid("nether_wart_block"),
+        solid("red_mushroom_block"), solid("mangrove_planks"),
+    ] },
+    BaseColor { id: 29, name: "color_black", blocks: &[
+        solid("black_concrete"), solid("black_wool"), solid("obsidian"), solid("coal_block"),
+        solid("blackstone"),
+    ] },
+    BaseColor { id: 30, name: "gold", blocks: &[
+        solid("gold_block"), solid("raw_gold_block"), solid("light_weighted_pressure_plate"),
+    ] },
+    BaseColor { id: 31, name: "diamond", blocks: &[
+        solid("diamond_block"), solid("prismarine_bricks"), solid("dark_prismarine"),
+    ] },
+    BaseColor { id: 32, name: "lapis", blocks: &[solid("lapis_block")] },
+    BaseColor { id: 33, name: "emerald", blocks: &[solid("emerald_block")] },
+    BaseColor { id: 34, name: "podzol", blocks: &[
+        solid("podzol"), solid("spruce_planks"), solid("spruce_log"),
+    ] },
+    BaseColor { id: 35, name: "nether", blocks: &[
+        solid("netherrack"), solid("magma_block"), solid("nether_quartz_ore"),
+    ] },
+    BaseColor { id: 36, name: "terracotta_white", blocks: &[
+        solid("white_terracotta"), solid("calcite"), solid("cherry_planks"),
+    ] },
+    BaseColor { id: 37, name: "terracotta_orange", blocks: &[solid("orange_terracotta")] },
+    BaseColor { id: 38, name: "terracotta_magenta", blocks: &[solid("magenta_terracotta")] },
+    BaseColor { id: 39, name: "terracotta_light_blue", blocks: &[solid("light_blue_terracotta")] },
+    BaseColor { id: 40, name: "terracotta_yellow", blocks: &[solid("yellow_terracotta")] },
+    BaseColor { id: 41, name: "terracotta_light_green", blocks: &[solid("lime_terracotta")] },
+    BaseColor { id: 42, name: "terracotta_pink", blocks: &[solid("pink_terracotta")] },
+    BaseColor { id: 43, name: "terracotta_gray", blocks: &[
+        solid("gray_terracotta"), solid("tuff"),
+    ] },
+    BaseColor { id: 44, name: "terracotta_light_gray", blocks: &[
+        solid("light_gray_terracotta"), solid("mud_bricks"),
+    ] },
+    BaseColor { id: 45, name: "terracotta_cyan", blocks: &[
+        solid("cyan_terracotta"), solid("mud"),
+    ] },
+    BaseColor { id: 46, name: "terracotta_purple", blocks: &[solid("purple_terracotta")] },
+    BaseColor { id: 47, name: "terracotta_blue", blocks: &[solid("blue_terracotta")] },
+    BaseColor { id: 48, name: "terracotta_brown", blocks: &[
+        solid("brown_terracotta"), solid("dripstone_block"),
+    ] },
+    BaseColor { id: 49, name: "terracotta_green", blocks: &[solid("green_terracotta")] },
+    BaseColor { id: 50, name: "terracotta_red", blocks: &[solid("red_terracotta")] },
+    BaseColor { id: 51, name: "terracotta_black", blocks: &[solid("black_terracotta")] },
+    BaseColor { id: 52, name: "crimson_nylium", blocks: &[solid("crimson_nylium")] },
+    BaseColor { id: 53, name: "crimson_stem", blocks: &[
+        solid("crimson_planks"), solid("crimson_stem"),
+    ] },
+    BaseColor { id: 54, name: "crimson_hyphae", blocks: &[solid("crimson_hyphae")] },
+    BaseColor { id: 55, name: "warped_nylium", blocks: &[solid("warped_nylium")] },
+    BaseColor { id: 56, name: "warped_stem", blocks: &[
+        solid("warped_planks"), solid("warped_stem"),
+    ] },
+    BaseColor { id: 57, name: "warped_hyphae", blocks: &[solid("warped_hyphae")] },
+    BaseColor { id: 58, name: "warped_wart_block", blocks: &[solid("warped_wart_block")] },
+    BaseColor { id: 59, name: "deepslate", blocks: &[
+        solid("deepslate"), solid("cobbled_deepslate"), solid("polished_deepslate"),
+    ] },
+    BaseColor { id: 60, name: "raw_iron", blocks: &[solid("raw_iron_block")] },
+    BaseColor { id: 61, name: "glow_lichen", blocks: &[
+        solid("verdant_froglight"), solid("glow_lichen"),
+    ] },
 ];
 
-/// Wrapper around KdTree3
-pub struct ColorTree(KdTree3<SearchableRgb>);
+/// A Minecraft (Java Edition) version that changed the set of map colors.
+/// Each variant covers every version up to the next variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum MinecraftVersion {
+    /// Beta 1.6, which added maps, to 1.7.1. These only have the original 13 base colors.
+    Beta1_6,
+    /// 1.7.2 added the dye, ore and nether colors.
+    V1_7_2,
+    /// 1.12 added the terracotta colors.
+    V1_12,
+    /// 1.16 added the crimson and warped colors.
+    V1_16,
+    /// 1.17 added deepslate, raw iron and glow lichen. This is still the latest set as of 1.21.
+    #[default]
+    V1_17,
+}
+
+impl MinecraftVersion {
+    /// Returns the highest base color ID supported by this version.
+    pub fn last_base_color(self) -> u8 {
+        match self {
+            MinecraftVersion::Beta1_6 => 13,
+            MinecraftVersion::V1_7_2 => 35,
+            MinecraftVersion::V1_12 => 51,
+            MinecraftVersion::V1_16 => 58,
+            MinecraftVersion::V1_17 => 61,
+        }
+    }
+
+    /// Returns the name of the first version with this set of colors.
+    pub fn name(self) -> &'static str {
+        match self {
+            MinecraftVersion::Beta1_6 => "Beta 1.6",
+            MinecraftVersion::V1_7_2 => "1.7.2",
+            MinecraftVersion::V1_12 => "1.12",
+            MinecraftVersion::V1_16 => "1.16",
+            MinecraftVersion::V1_17 => "1.17",
+        }
+    }
+}
+
+/// Which of the four shades of each base color can be used.
+///
+/// The shade of a map pixel depends on the height of the block compared to the block north of it:
+/// shade 0 is lower, shade 1 is level and shade 2 is higher. Shade 3 can't be produced by blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShadeMode {
+    /// Only shade 1, for map art built on a flat surface.
+    Flat,
+    /// Shades 0 to 2, for map art built as a staircase.
+    Staircase,
+    /// All four shades, for map art that is only written to map files.
+    #[default]
+    Full,
+}
+
+impl ShadeMode {
+    /// Returns whether the given shade (0 to 3) can be used.
+    pub fn includes(self, shade: u8) -> bool {
+        match self {
+            ShadeMode::Flat => shade == 1,
+            ShadeMode::Staircase => shade <= 2,
+            ShadeMode::Full => shade <= 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShadeMode::Flat => "flat",
+            ShadeMode::Staircase => "staircase",
+            ShadeMode::Full => "full",
+        }
+    }
+}
+
+/// Options for building a palette of Minecraft map colors.
+#[derive(Debug, Clone, Default)]
+pub struct MapPaletteOptions {
+    /// The game version whose colors are used.
+    pub version: MinecraftVersion,
+    /// The shades of each base color that are used.
+    pub shades: ShadeMode,
+    /// Base color IDs that aren't used.
+    pub excluded_base_colors: Vec<u8>,
+    /// Block IDs (e.g. `water`) that aren't available.
+    /// Base colors without any available block aren't used.
+    pub excluded_blocks: Vec<String>,
+    /// Whether blocks affected by gravity, like sand and gravel, are unavailable.
+    pub exclude_gravity_blocks: bool,
+}
+
+impl MapPaletteOptions {
+    /// Returns the blocks of a base color that are available with these options.
+    pub fn available_blocks<'a>(
+        &'a self,
+        base_color: &'a BaseColor,
+    ) -> impl Iterator<Item = &'a MapBlock> {
+        base_color.blocks.iter().filter(move |block| {
+            let excluded = self.excluded_blocks.iter().any(|id| id == block.id);
+            !(excluded || self.exclude_gravity_blocks && block.gravity)
+        })
+    }
+
+    /// Returns whether a base color can be used with these options.
+    pub fn includes_base_color(&self, base_color: &BaseColor) -> bool {
+        base_color.id <= self.version.last_base_color()
+            && !self.excluded_base_colors.contains(&base_color.id)
+            && self.available_blocks(base_color).next().is_some()
+    }
+}
+
+/// A color in a palette along with the index that identifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteColor {
+    /// For Minecraft palettes, this is the map color ID (`base * 4 + shade`).
+    pub index: u8,
+    pub rgb: Rgb<u8>,
+}
+
+/// A list of colors that images can be converted to, along with a name describing it.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    name: String,
+    colors: Vec<PaletteColor>,
+}
+
+impl Palette {
+    /// Creates a palette from a list of colors, indexed by their position in the list.
+    ///
+    /// # Panics
+    /// Panics if there are more than 256 colors.
+    pub fn new(name: impl Into<String>, colors: Vec<Rgb<u8>>) -> Self {
+        assert!(colors.len() <= 256, "a palette can have at most 256 colors");
+        let colors = colors
+            .into_iter()
+            .enumerate()
+            .map(|(index, rgb)| PaletteColor {
+                index: index as u8,
+                rgb,
+            })
+            .collect();
+        Palette::from_indexed(name, colors)
+    }
+
+    /// Creates a palette from colors that already have an index assigned.
+    pub fn from_indexed(name: impl Into<String>, colors: Vec<PaletteColor>) -> Self {
+        Palette {
+            name: name.into(),
+            colors,
+        }
+    }
+
+    /// Returns the palette of all Minecraft map colors in the latest version,
+    /// indexed by their map color ID.
+    pub fn minecraft() -> Self {
+        Palette::minecraft_with(&MapPaletteOptions::default())
+    }
+
+    /// Returns the palette of Minecraft map colors selected by the options,
+    /// indexed by their map color ID.
+    pub fn minecraft_with(options: &MapPaletteOptions) -> Self {
+        let colors = COLOR_LIST
+            .iter()
+            .enumerate()
+            .map(|(i, &rgb)| PaletteColor {
+                index: FIRST_COLOR_ID + i as u8,
+                rgb,
+            })
+            .filter(|color| options.includes_base_color(&BASE_COLORS[color.index as usize / 4 - 1]))
+            .filter(|color| options.shades.includes(color.index % 4))
+            .collect();
+        let name = format!(
+            "Minecraft {} map colors ({} shades)",
+            options.version.name(),
+            options.shades.name()
+        );
+        Palette::from_indexed(name, colors)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn colors(&self) -> &[PaletteColor] {
+        &self.colors
+    }
+
+    /// Returns the color with the given index, if it is in the palette.
+    pub fn get(&self, index: u8) -> Option<Rgb<u8>> {
+        self.colors
+            .iter()
+            .find(|color| color.index == index)
+            .map(|color| color.rgb)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+/// The distance used to find the closest color in a palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DistanceMetric {
+    /// Euclidean distance between the sRGB values.
+    #[default]
+    Rgb,
+    /// Weighted sRGB distance that depends on the average red value ("redmean").
+    Redmean,
+    /// Euclidean distance in CIELAB (CIE76).
+    Cie76,
+    /// The CIEDE2000 color difference.
+    Ciede2000,
+    /// Euclidean distance in OKLab.
+    Oklab,
+}
+
+impl DistanceMetric {
+    /// Returns the coordinates of the color in a space where this metric is the Euclidean
+    /// distance, or `None` if there is no such space.
+    fn point(self, color: &Rgb<u8>) -> Option<[f32; 3]> {
+        match self {
+            DistanceMetric::Rgb => Some(color.0.map(|channel| channel as f32)),
+            DistanceMetric::Cie76 => Some(to_lab(color)),
+            DistanceMetric::Oklab => Some(to_oklab(color)),
+            DistanceMetric::Redmean | DistanceMetric::Ciede2000 => None,
+        }
+    }
+
+    /// Returns a value that orders colors by their distance in this metric.
+    /// `a_lab` and `b_lab` are the CIELAB coordinates of the colors.
+    fn distance(self, a: &Rgb<u8>, a_lab: [f32; 3], b: &Rgb<u8>, b_lab: [f32; 3]) -> f32 {
+        match self {
+            DistanceMetric::Redmean => redmean_squared(a, b),
+            DistanceMetric::Ciede2000 => ciede2000(a_lab, b_lab),
+            _ => {
+                let a = self.point(a).unwrap();
+                let b = self.point(b).unwrap();
+                (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+            }
+        }
+    }
+}
+
+/// Finds the closest color of a palette for each pixel, which is what the converters need.
+/// Implemented by `ColorTree` and by `ColorLut`, which caches the results of a `ColorTree`.
+pub trait ColorSearch: Send + Sync {
+    /// Returns the palette that is searched.
+    fn palette(&self) -> &Arc<Palette>;
+
+    /// Returns the closest color in the palette (including its index) and the distance to it.
+    fn find_closest(&self, color: &Rgb<u8>) -> (PaletteColor, [i16; 3]);
+}
+
+/// How `ColorTree` searches for the closest color.
+enum Search {
+    /// A kd-tree in the color space of a Euclidean metric.
+    Tree(KdTree3<SearchableRgb>),
+    /// A linear search through all colors for metrics that aren't Euclidean.
+    /// Contains the CIELAB coordinates of each color.
+    Exact(Vec<(PaletteColor, [f32; 3])>),
+}
+
+/// Searches the colors of a palette for the closest color, using a kd-tree where possible.
+pub struct ColorTree {
+    search: Search,
+    metric: DistanceMetric,
+    palette: Arc<Palette>,
+}
 
 impl ColorTree {
-    /// Returns the closest color in the Minecraft color palette and the distance to it.
-    pub fn find_closest(&self, color: &Rgb<u8>) -> (Rgb<u8>, [i16; 3]) {
-        // Cast to MinecraftRgb to use the KdTree, the index is ignored
-        let to_search = SearchableRgb(*color);
-        let nearest = self.0.nearest(&to_search).unwrap();
-
-        // KdTree returns the squared distance in `nearest`, but we want the absolute distance
-        let distance = [
-            color.0[0] as i16 - nearest.item.0[0] as i16,
-            color.0[1] as i16 - nearest.item.0[1] as i16,
-            color.0[2] as i16 - nearest.item.0[2] as i16,
-        ];
-
-        // Return the MC index of the color and the distance
-        (nearest.item.0, distance)
+    /// Builds the kd-tree from the colors of the given palette, using Euclidean RGB distance.
+    ///
+    /// # Panics
+    /// Panics if the palette is empty.
+    pub fn new(palette: Arc<Palette>) -> Self {
+        ColorTree::with_metric(palette, DistanceMetric::Rgb)
+    }
+
+    /// Builds a search for the colors of the given palette using the given distance metric.
+    ///
+    /// # Panics
+    /// Panics if the palette is empty.
+    pub fn with_metric(palette: Arc<Palette>, metric: DistanceMetric) -> Self {
+        assert!(
+            !palette.is_empty(),
+            "cannot build a color tree from an empty palette"
+        );
+
+        let search = if metric.point(&Rgb([0, 0, 0])).is_some() {
+            let points = palette
+                .colors()
+                .iter()
+                .map(|&color| SearchableRgb(color, metric.point(&color.rgb).unwrap()))
+                .collect();
+            Search::Tree(KdTree3::build_by_ordered_float(points))
+        } else {
+            let colors = palette
+                .colors()
+                .iter()
+                .map(|&color| (color, to_lab(&color.rgb)))
+                .collect();
+            Search::Exact(colors)
+        };
+
+        ColorTree {
+            search,
+            metric,
+            palette,
+        }
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
     }
 }
 
-static COLOR_TREE: OnceLock<ColorTree> = OnceLock::new();
+impl ColorSearch for ColorTree {
+    fn palette(&self) -> &Arc<Palette> {
+        &self.palette
+    }
+
+    fn find_closest(&self, color: &Rgb<u8>) -> (PaletteColor, [i16; 3]) {
+        let nearest = match &self.search {
+            Search::Tree(tree) => {
+                // Cast to SearchableRgb to use the KdTree, the index is ignored
+                let to_search = SearchableRgb(
+                    PaletteColor {
+                        index: 0,
+                        rgb: *color,
+                    },
+                    self.metric.point(color).unwrap(),
+                );
+                tree.nearest(&to_search).unwrap().item.0
+            }
+            Search::Exact(colors) => {
+                let lab = to_lab(color);
+                let (nearest, _) = colors
+                    .iter()
+                    .map(|(candidate, candidate_lab)| {
+                        let distance =
+                            self.metric
+                                .distance(color, lab, &candidate.rgb, *candidate_lab);
+                        (candidate, distance)
+                    })
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .unwrap();
+                *nearest
+            }
+        };
+
+        // The search returns the squared distance, but we want the difference in RGB
+        (nearest, rgb_difference(color, &nearest.rgb))
+    }
+}
 
-pub fn get_color_tree() -> &'static ColorTree {
-    COLOR_TREE.get_or_init(|| ColorTree(KdTree3::build(Vec::from(COLOR_LIST))))
+/// Returns the difference `a - b` in each RGB channel.
+pub(crate) fn rgb_difference(a: &Rgb<u8>, b: &Rgb<u8>) -> [i16; 3] {
+    [
+        a.0[0] as i16 - b.0[0] as i16,
+        a.0[1] as i16 - b.0[1] as i16,
+        a.0[2] as i16 - b.0[2] as i16,
+    ]
 }
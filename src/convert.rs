@@ -1,4 +1,8 @@
+use crate::color_space::{linear_to_srgb, srgb_to_linear};
+use crate::colors::Palette;
 use image::{Rgb, RgbImage};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::Arc;
 
 /// An array of tuples containing the offset and the factor for the Floyd-Steinberg dithering algorithm.
 pub const DITHERING_MATRIX: [([i32; 2], f32); 4] = [
@@ -8,24 +12,503 @@ pub const DITHERING_MATRIX: [([i32; 2], f32); 4] = [
     ([1, 1], 0.0625),
 ];
 
+/// An error-diffusion kernel: the offsets of the pixels that receive part of the error of a pixel,
+/// relative to that pixel, and the factor of the error that each of them receives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    taps: Vec<([i32; 2], f32)>,
+}
+
+impl Kernel {
+    /// Creates a kernel from `[x, y]` offsets and factors.
+    ///
+    /// # Panics
+    /// Panics if an offset points at the pixel itself or at a pixel before it in scan order,
+    /// which has been converted already.
+    pub fn new(taps: Vec<([i32; 2], f32)>) -> Self {
+        for ([x, y], _) in &taps {
+            assert!(
+                *y > 0 || (*y == 0 && *x > 0),
+                "kernel offset [{x}, {y}] doesn't point at a pixel after the current one"
+            );
+        }
+        Kernel { taps }
+    }
+
+    /// Creates a kernel from a matrix of weights in the form they are usually written in:
+    /// the current pixel is in the middle of the first row, and each weight is divided by `divisor`.
+    /// Zero weights are skipped.
+    ///
+    /// # Panics
+    /// Panics if the rows don't all have the same odd length,
+    /// or if a weight is at or before the current pixel.
+    pub fn from_matrix(matrix: &[&[f32]], divisor: f32) -> Self {
+        let width = matrix.first().map_or(1, |row| row.len());
+        assert!(
+            width % 2 == 1 && matrix.iter().all(|row| row.len() == width),
+            "the rows of a kernel matrix must all have the same odd length"
+        );
+
+        let center = (width / 2) as i32;
+        let taps = matrix
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &weight)| weight != 0.0)
+                    .map(move |(x, &weight)| ([x as i32 - center, y as i32], weight / divisor))
+            })
+            .collect();
+        Kernel::new(taps)
+    }
+
+    pub fn floyd_steinberg() -> Self {
+        Kernel::new(DITHERING_MATRIX.to_vec())
+    }
+
+    pub fn jarvis_judice_ninke() -> Self {
+        Kernel::from_matrix(
+            &[
+                &[0.0, 0.0, 0.0, 7.0, 5.0],
+                &[3.0, 5.0, 7.0, 5.0, 3.0],
+                &[1.0, 3.0, 5.0, 3.0, 1.0],
+            ],
+            48.0,
+        )
+    }
+
+    pub fn stucki() -> Self {
+        Kernel::from_matrix(
+            &[
+                &[0.0, 0.0, 0.0, 8.0, 4.0],
+                &[2.0, 4.0, 8.0, 4.0, 2.0],
+                &[1.0, 2.0, 4.0, 2.0, 1.0],
+            ],
+            42.0,
+        )
+    }
+
+    pub fn burkes() -> Self {
+        Kernel::from_matrix(
+            &[&[0.0, 0.0, 0.0, 8.0, 4.0], &[2.0, 4.0, 8.0, 4.0, 2.0]],
+            32.0,
+        )
+    }
+
+    /// The three-row Sierra kernel.
+    pub fn sierra() -> Self {
+        Kernel::from_matrix(
+            &[
+                &[0.0, 0.0, 0.0, 5.0, 3.0],
+                &[2.0, 4.0, 5.0, 4.0, 2.0],
+                &[0.0, 2.0, 3.0, 2.0, 0.0],
+            ],
+            32.0,
+        )
+    }
+
+    pub fn two_row_sierra() -> Self {
+        Kernel::from_matrix(
+            &[&[0.0, 0.0, 0.0, 4.0, 3.0], &[1.0, 2.0, 3.0, 2.0, 1.0]],
+            16.0,
+        )
+    }
+
+    pub fn sierra_lite() -> Self {
+        Kernel::from_matrix(&[&[0.0, 0.0, 2.0], &[1.0, 1.0, 0.0]], 4.0)
+    }
+
+    /// Atkinson's kernel only diffuses 3/4 of the error, which gives more contrast.
+    pub fn atkinson() -> Self {
+        Kernel::from_matrix(
+            &[
+                &[0.0, 0.0, 0.0, 1.0, 1.0],
+                &[0.0, 1.0, 1.0, 1.0, 0.0],
+                &[0.0, 0.0, 1.0, 0.0, 0.0],
+            ],
+            8.0,
+        )
+    }
+
+    /// A cheaper variant of Floyd-Steinberg that only diffuses to the right and down.
+    pub fn false_floyd_steinberg() -> Self {
+        Kernel::from_matrix(&[&[0.0, 0.0, 3.0], &[0.0, 3.0, 2.0]], 8.0)
+    }
+
+    /// Returns the kernel for rows that are scanned from right to left.
+    pub fn mirrored(&self) -> Self {
+        Kernel {
+            taps: self
+                .taps
+                .iter()
+                .map(|&([x, y], factor)| ([-x, y], factor))
+                .collect(),
+        }
+    }
+
+    pub fn taps(&self) -> &[([i32; 2], f32)] {
+        &self.taps
+    }
+
+    /// Returns the number of rows below the current one that receive error.
+    pub fn depth(&self) -> u32 {
+        self.taps
+            .iter()
+            .map(|([_, y], _)| *y as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how many columns to the left of the current pixel the kernel reaches
+    /// in the rows below. A column of the next rows is final once the pixel
+    /// this many columns to the right of it has been converted.
+    pub fn left_reach(&self) -> u32 {
+        self.taps
+            .iter()
+            .filter(|([_, y], _)| *y > 0)
+            .map(|([x, _], _)| (-x).max(0) as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how many pixels a row must be ahead of the row below it before that row
+    /// can convert a pixel, when rows are converted at the same time.
+    /// Every row is at least this far ahead of the next one, so a row `n` rows further up
+    /// is `n` times as far ahead, which covers the taps that reach `n` rows down.
+    pub fn row_lag(&self) -> u32 {
+        self.taps
+            .iter()
+            .filter(|([_, y], _)| *y > 0)
+            .map(|([x, y], _)| ((-x).max(0) as u32).div_ceil(*y as u32))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how many columns to the right of the current pixel the kernel reaches.
+    pub fn right_reach(&self) -> u32 {
+        self.taps
+            .iter()
+            .map(|([x, _], _)| (*x).max(0) as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how many pixels a row must be ahead of the row below it so that, when rows add
+    /// their error to the pixels below at the same time, every pixel still receives the errors
+    /// in scan order: a row only adds to a pixel once the rows above are done adding to it.
+    /// This is at least `row_lag`, which is the case of the pixel that is being converted.
+    pub fn ordered_row_lag(&self) -> u32 {
+        // The pixels that a pixel adds error to, and the pixel itself, which reads its own value
+        let targets = self.taps.iter().map(|(offset, _)| *offset).chain([[0, 0]]);
+        targets
+            .flat_map(|[x, y]| {
+                self.taps
+                    .iter()
+                    .filter(move |([_, other_y], _)| *other_y > y)
+                    .map(move |([other_x, other_y], _)| {
+                        ((x - other_x).max(0) as u32).div_ceil((other_y - y) as u32)
+                    })
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Kernel::floyd_steinberg()
+    }
+}
+
+/// The color space that the error is diffused in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorSpace {
+    /// Diffuses the error between the gamma-encoded sRGB values.
+    #[default]
+    Srgb,
+    /// Decodes the pixels to linear light before diffusing the error,
+    /// which keeps dithered midtones from coming out too dark.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Decodes a pixel to the working values that the error is diffused in, in the range [0, 1].
+    pub fn decode(self, color: &Rgb<u8>) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => color.0.map(|channel| channel as f32 / 255.0),
+            ColorSpace::Linear => color.0.map(srgb_to_linear),
+        }
+    }
+
+    /// Encodes working values back to the sRGB color that is matched against the palette.
+    /// This is the only place where the values are rounded and clamped.
+    pub fn encode(self, values: [f32; 3]) -> Rgb<u8> {
+        match self {
+            ColorSpace::Srgb => {
+                Rgb(values.map(|value| (value * 255.0).round().clamp(0.0, 255.0) as u8))
+            }
+            ColorSpace::Linear => Rgb(values.map(linear_to_srgb)),
+        }
+    }
+
+    /// Returns the error between working values and the palette color they were converted to.
+    pub fn error(self, values: [f32; 3], closest: &Rgb<u8>) -> [f32; 3] {
+        let closest = self.decode(closest);
+        [0, 1, 2].map(|i| values[i] - closest[i])
+    }
+}
+
+/// Options for how the error-diffusion converters spread the error.
+///
+/// All error-diffusion converters give the same palette indices, bit for bit, for the same
+/// options. They all follow the reference order: the rows are converted from top to bottom,
+/// each in its scan direction, and each pixel starts at its decoded color and receives the
+/// errors of the pixels whose kernel reaches it one at a time, in the order that those pixels
+/// are converted in. Since adding floats isn't associative, the parallel converters keep this
+/// order instead of adding up errors as they come in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffusionOptions {
+    pub color_space: ColorSpace,
+    pub kernel: Kernel,
+    /// Scans every other row from right to left with a mirrored kernel,
+    /// which avoids the diagonal artifacts of always diffusing the error in the same direction.
+    /// A row can't start before the row above it is done when they go in opposite directions,
+    /// so the parallel converters convert one row at a time.
+    pub serpentine: bool,
+    /// The factor of the error that is diffused, from 0.0 (no dithering) to 1.0 (all of it).
+    /// Less dithering keeps flat areas of graphics and logos clean.
+    pub strength: f32,
+    /// Clamps each channel of the diffused error to `[-limit, limit]`, in working values
+    /// from 0.0 to 1.0, so large errors don't smear into the surrounding pixels.
+    pub error_limit: Option<f32>,
+}
+
+impl DiffusionOptions {
+    /// Returns the part of the error of a pixel that is diffused, after the strength and limit.
+    pub fn attenuate(&self, errors: [f32; 3]) -> [f32; 3] {
+        attenuate(errors, self.strength, self.error_limit)
+    }
+}
+
+/// Scales the error of a pixel by `strength` and clamps each channel to `[-limit, limit]`.
+pub(crate) fn attenuate(errors: [f32; 3], strength: f32, error_limit: Option<f32>) -> [f32; 3] {
+    errors.map(|error| {
+        let error = error * strength;
+        match error_limit {
+            Some(limit) => error.clamp(-limit, limit),
+            None => error,
+        }
+    })
+}
+
+impl Default for DiffusionOptions {
+    fn default() -> Self {
+        DiffusionOptions {
+            color_space: ColorSpace::default(),
+            kernel: Kernel::default(),
+            serpentine: false,
+            strength: 1.0,
+            error_limit: None,
+        }
+    }
+}
+
+/// The direction that each row is scanned in and the kernel for that direction.
+pub(crate) struct RowScan {
+    kernel: Kernel,
+    mirrored: Kernel,
+    serpentine: bool,
+    width: u32,
+}
+
+impl RowScan {
+    pub(crate) fn new(options: &DiffusionOptions, width: u32) -> Self {
+        RowScan {
+            kernel: options.kernel.clone(),
+            mirrored: options.kernel.mirrored(),
+            serpentine: options.serpentine,
+            width,
+        }
+    }
+
+    /// Returns whether the row is scanned from right to left.
+    pub(crate) fn is_reversed(&self, y: u32) -> bool {
+        self.serpentine && y % 2 == 1
+    }
+
+    /// Returns the column of the `i`th pixel in the scan of the row.
+    pub(crate) fn column(&self, y: u32, i: u32) -> u32 {
+        if self.is_reversed(y) {
+            self.width - 1 - i
+        } else {
+            i
+        }
+    }
+
+    /// Returns the kernel in image coordinates for the row.
+    pub(crate) fn kernel(&self, y: u32) -> &Kernel {
+        if self.is_reversed(y) {
+            &self.mirrored
+        } else {
+            &self.kernel
+        }
+    }
+
+    /// Returns the offsets from a pixel of row `y` to the pixels that diffuse error to it,
+    /// and the factors of their errors. The offsets are in the order that the pixels are
+    /// converted in by `SingleThreadedConverter`, so the errors are added up in the same order.
+    pub(crate) fn gather_taps(&self, y: u32) -> Vec<([i32; 2], f32)> {
+        let mut taps = Vec::new();
+        for dy in (0..=self.kernel.depth().min(y)).rev() {
+            let source_y = y - dy;
+            let start = taps.len();
+            taps.extend(
+                self.kernel(source_y)
+                    .taps()
+                    .iter()
+                    .filter(|([_, tap_y], _)| *tap_y == dy as i32),
+            );
+
+            // A pixel further to the left has a larger offset, and is converted first
+            // unless its row is scanned from right to left
+            let row_taps = &mut taps[start..];
+            if self.is_reversed(source_y) {
+                row_taps.sort_by_key(|([dx, _], _)| *dx);
+            } else {
+                row_taps.sort_by_key(|([dx, _], _)| -dx);
+            }
+        }
+        taps
+    }
+}
+
 /// A trait for converting an image to the target color palette.
 pub trait Converter {
     /// Returns a converted image in the target color palette.
-    fn convert(&self, image: RgbImage) -> RgbImage;
+    fn convert(&self, image: RgbImage) -> IndexedImage;
 }
 
-/// A helper function that adds the error to the target pixel in all three channels with the given factor.
-pub fn distribute_rgb_channels(pixel: &mut Rgb<u8>, errors: [f32; 3], factor: f32) {
-    for (channel, error) in pixel.0.iter_mut().zip(errors) {
-        let value = *channel as f32 / 256.0 + error * factor;
-        if value >= 1.0 {
-            *channel = 255;
-        } else if value <= 0.0 {
-            *channel = 0;
-        } else {
-            *channel = (value * 256.0) as u8;
+/// The thread pool that a parallel converter runs on.
+/// By default it runs on the current rayon pool, i.e. the global pool or the pool it's called from.
+#[derive(Clone, Default)]
+pub struct Parallelism {
+    thread_pool: Option<Arc<ThreadPool>>,
+}
+
+impl Parallelism {
+    /// Runs `op` on the thread pool, or on the current rayon pool if there isn't one.
+    /// Everything that `op` runs in parallel, including `rayon::scope` and
+    /// `rayon::current_num_threads`, then uses that pool.
+    pub(crate) fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(op),
+            None => op(),
         }
     }
 }
 
+/// A converter that converts the pixels in parallel on a rayon thread pool.
+pub trait ParallelConverter: Converter + Sized {
+    /// Returns the thread pool choice that the builder methods below change.
+    fn parallelism_mut(&mut self) -> &mut Parallelism;
 
+    /// Runs the converter on `thread_pool` instead of the current rayon pool.
+    fn with_thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.parallelism_mut().thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Runs the converter on a new thread pool with the given number of threads.
+    ///
+    /// # Panics
+    /// Panics if `threads` is 0 or the threads can't be spawned.
+    fn with_threads(self, threads: usize) -> Self {
+        assert!(threads > 0, "a thread pool needs at least one thread");
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed to build the thread pool");
+        self.with_thread_pool(Arc::new(thread_pool))
+    }
+}
+
+/// An image where each pixel is stored as the index of a color in a palette.
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    width: u32,
+    height: u32,
+    indices: Vec<u8>,
+    palette: Arc<Palette>,
+}
+
+impl IndexedImage {
+    /// Creates an indexed image from row-major palette indices.
+    ///
+    /// # Panics
+    /// Panics if the number of indices doesn't match the dimensions.
+    pub fn new(width: u32, height: u32, indices: Vec<u8>, palette: Arc<Palette>) -> Self {
+        assert_eq!(
+            indices.len(),
+            (width * height) as usize,
+            "indices must contain one entry per pixel"
+        );
+        IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Returns the palette indices of all pixels in row-major order.
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    pub fn palette(&self) -> &Arc<Palette> {
+        &self.palette
+    }
+
+    /// Returns the palette index of the pixel at the given coordinates.
+    pub fn get_index(&self, x: u32, y: u32) -> u8 {
+        self.indices[(y * self.width + x) as usize]
+    }
+
+    /// Converts the image back to RGB, e.g. to save a preview.
+    /// Indices that aren't in the palette become black.
+    pub fn to_rgb_image(&self) -> RgbImage {
+        let mut lookup = [Rgb([0, 0, 0]); 256];
+        for color in self.palette.colors() {
+            lookup[color.index as usize] = color.rgb;
+        }
+
+        let mut image = RgbImage::new(self.width, self.height);
+        for (pixel, &index) in image.pixels_mut().zip(&self.indices) {
+            *pixel = lookup[index as usize];
+        }
+        image
+    }
+}
+
+/// A helper function that adds the error to the working values of the target pixel
+/// in all three channels with the given factor.
+/// The values are kept at full precision and may leave the range [0, 1],
+/// so no error is lost before the pixel is quantized.
+pub fn distribute_rgb_channels(pixel: &mut [f32; 3], errors: [f32; 3], factor: f32) {
+    for (value, error) in pixel.iter_mut().zip(errors) {
+        *value += error * factor;
+    }
+}
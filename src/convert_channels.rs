@@ -1,110 +1,220 @@
-use std::sync::{Arc, Mutex};
-use crossbeam::channel::{Receiver, Sender, unbounded};
-use image::{Rgb, RgbImage};
+use std::sync::Arc;
+use crossbeam::channel::{Receiver, unbounded};
+use image::RgbImage;
 use parking_lot::RwLock;
 use rayon::Scope;
-use crate::colors::get_color_tree;
-use crate::convert::{Converter, distribute_rgb_channels, DITHERING_MATRIX};
+use crate::colors::ColorSearch;
+use crate::convert::{
+    Converter, DiffusionOptions, distribute_rgb_channels, IndexedImage, ParallelConverter, Parallelism, RowScan,
+};
 
 /// A converter that converts the image to the target color palette
 /// using multiple threads and channels to communicate between them.
-pub struct ChannelConverter;
+/// Each row sends the working values of the rows below it to the next row once it is done
+/// adding its error to them, so every pixel receives the errors in scan order.
+/// The palette indices are written to a shared output behind a lock.
+pub struct ChannelConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+    parallelism: Parallelism,
+}
 
 impl ChannelConverter {
-    pub fn new() -> Self {
-        ChannelConverter {}
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        ChannelConverter {
+            tree,
+            options: DiffusionOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ParallelConverter for ChannelConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
     }
 }
 
 impl Converter for ChannelConverter {
-    fn convert(&self, image: RgbImage) -> RgbImage {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
         let (width, height) = image.dimensions();
-        let orginal_image = Arc::new(RwLock::new(image));
-
-
-        rayon::scope(|s| {
-            s.spawn(|s| {
-                let cloned_image_ref = orginal_image.clone();
-                thread(s, cloned_image_ref, width, height, 0, None)
+        let orginal_image = Arc::new(image);
+        let indices = Arc::new(RwLock::new(vec![0; (width * height) as usize]));
+
+        let scan = RowScan::new(&self.options, width);
+        if height > 0 {
+            self.parallelism.install(|| {
+                rayon::scope(|s| {
+                    s.spawn(|s| {
+                        let cloned_image_ref = orginal_image.clone();
+                        let cloned_indices_ref = indices.clone();
+                        thread(
+                            s,
+                            &*self.tree,
+                            &self.options,
+                            &scan,
+                            cloned_image_ref,
+                            cloned_indices_ref,
+                            width,
+                            height,
+                            0,
+                            None,
+                        )
+                    });
+                });
             });
-        });
+        }
 
-        RwLock::into_inner(Arc::into_inner(orginal_image).unwrap())
+        let indices = RwLock::into_inner(Arc::into_inner(indices).unwrap());
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
     }
 }
 
-fn thread(
-    s: &Scope,
-    image: Arc<RwLock<RgbImage>>,
+#[allow(clippy::too_many_arguments)]
+fn thread<'s>(
+    s: &Scope<'s>,
+    tree: &'s dyn ColorSearch,
+    options: &'s DiffusionOptions,
+    scan: &'s RowScan,
+    image: Arc<RgbImage>,
+    indices: Arc<RwLock<Vec<u8>>>,
     width: u32,
     height: u32,
     y: u32,
-    error_recv: Option<Receiver<[f32; 3]>>,
+    values_recv: Option<Receiver<[f32; 3]>>,
 ) {
-    let mut next_pixel_error: [f32; 3] = [0.0; 3];
-
-    // Ring buffer for the three pixels under us, to be sent to the next thread
-    // errors_ring[ring_idx] will be the leftmost pixel
-    let mut ring_idx = 0_usize;
-    let mut errors_ring: [[f32; 3]; 3] = [[0.0; 3]; 3];
-    let (next_error_send, next_error_recv) = unbounded::<[f32; 3]>();
-    let mut next_error_recv_opt = Some(next_error_recv);
-
-    for x in 0..width {
-        if let Some(error_recv) = &error_recv {
-            if let Ok(received_error) = error_recv.recv() {
-                for i in 0..3 {
-                    next_pixel_error[i] += received_error[i];
-                }
-            } else {
-                // Don't care. The previous thread just ended, but we can continue with 0 error.
+    let color_space = options.color_space;
+    let depth = options.kernel.depth() as usize;
+
+    // This row can't add error to a column anymore once it has converted
+    // the pixel this many pixels further along the row
+    let reach = options.kernel.left_reach().min(width.saturating_sub(1));
+
+    // A pixel adds error to the columns up to this many pixels further along the row,
+    // so it needs the values of those columns from the previous row before it is converted
+    let ahead = options.kernel.right_reach();
+
+    // In a serpentine scan the next row starts where this row ends, so it has to wait for all of it
+    let spawn_at = if options.serpentine {
+        width.saturating_sub(1)
+    } else {
+        (reach + ahead).min(width.saturating_sub(1))
+    };
+
+    // values[k][x] is the working value of the pixel in column x, k rows below this one,
+    // with the error of this row and the rows above added to it
+    // The rows below are sent to the next thread one column at a time in scan order,
+    // as `depth` messages for the rows 1 to `depth` below this one
+    let decode_row = |y: u32| -> Vec<[f32; 3]> {
+        if y < height {
+            (0..width)
+                .map(|x| color_space.decode(image.get_pixel(x, y)))
+                .collect()
+        } else {
+            vec![[0.0; 3]; width as usize]
+        }
+    };
+    let mut values: Vec<Vec<[f32; 3]>> = if values_recv.is_some() {
+        // The previous thread sends all rows but the last, which no row above reaches
+        let mut values = vec![vec![[0.0; 3]; width as usize]; depth];
+        values.push(decode_row(y + depth as u32));
+        values
+    } else {
+        (0..=depth as u32).map(|k| decode_row(y + k)).collect()
+    };
+
+    let (next_values_send, next_values_recv) = unbounded::<[f32; 3]>();
+    let mut next_values_recv_opt = Some(next_values_recv);
+    let send_column = |values: &[Vec<[f32; 3]>], i: u32| {
+        if y < height - 1 {
+            let x = scan.column(y, i) as usize;
+            for row in &values[1..] {
+                next_values_send.send(row[x]).unwrap();
             }
         }
+    };
+
+    // The previous row of a serpentine scan sends its columns in the opposite order,
+    // so this row needs all of them before it can start
+    let mut received = 0;
+    if let Some(values_recv) = &values_recv {
+        if options.serpentine {
+            for i in 0..width {
+                receive_column(values_recv, &mut values, scan.column(y - 1, i));
+            }
+            received = width;
+        }
+    }
 
-        let mut color;
-        {
-            // Get original pixel color from image
-            let image = image.read();
-            color = image.get_pixel(x, y).to_owned();
+    for i in 0..width {
+        let x = scan.column(y, i);
 
-            // Apply dithering error from previous thread (the factor was precomputed)
-            distribute_rgb_channels(&mut color, next_pixel_error, 1.0);
+        // Only rows that aren't serpentine get here with columns left to receive,
+        // and their columns are in the same order as their pixels
+        if let Some(values_recv) = &values_recv {
+            while received < width.min(i + ahead + 1) {
+                receive_column(values_recv, &mut values, received);
+                received += 1;
+            }
         }
 
+        // The original pixel color with the dithering error from previous threads and this one
+        let pixel_values = values[0][x as usize];
+
         // Find the closest MC color
-        let (closest_color, difference) = get_color_tree().find_closest(&color);
+        let (closest_color, _) = tree.find_closest(&color_space.encode(pixel_values));
 
         // Apply converted pixel
         {
-            let mut image = image.write();
-            *image.get_pixel_mut(x, y) = closest_color;
+            let mut indices = indices.write();
+            indices[(y * width + x) as usize] = closest_color.index;
         }
 
-        let errors = difference.map(|err| err as f32 / 256.0);
+        let pixel_errors = options.attenuate(color_space.error(pixel_values, &closest_color.rgb));
 
-        // Propagate errors and communicate with the next thread
-        for i in 0..3 {
-            next_pixel_error[i] = errors[i] * DITHERING_MATRIX[0].1;
-            errors_ring[ring_idx][i] += errors[i] * DITHERING_MATRIX[1].1;
-            errors_ring[(ring_idx + 1) % 3][i] += errors[i] * DITHERING_MATRIX[2].1;
+        // Propagate errors to the pixels of the kernel
+        for &([vx, vy], factor) in scan.kernel(y).taps() {
+            let x = x as i32 + vx;
 
-            // The last one we can overwrite because it was sent over in the last iteration
-            errors_ring[(ring_idx + 2) % 3][i] = errors[i] * DITHERING_MATRIX[3].1;
+            // Check bounds within image (vy will never be negative)
+            if x < 0 || x as u32 >= width || y + vy as u32 >= height {
+                continue;
+            }
+            distribute_rgb_channels(&mut values[vy as usize][x as usize], pixel_errors, factor);
         }
 
-        if x == 1 && next_error_recv_opt.is_some() && y < height - 1 {
+        if i == spawn_at && next_values_recv_opt.is_some() && y < height - 1 {
             let image = image.clone();
-            let next_error_recv_opt = next_error_recv_opt.take();
+            let indices = indices.clone();
+            let next_values_recv_opt = next_values_recv_opt.take();
             s.spawn(move |s| {
-                thread(s, image, width, height,y + 1, next_error_recv_opt)
+                thread(s, tree, options, scan, image, indices, width, height, y + 1, next_values_recv_opt)
             });
         }
 
-        // Send the error
-        next_error_send.send(errors_ring[ring_idx]).unwrap();
+        // Send the values of the column that are now final
+        if i >= reach {
+            send_column(&values, i - reach);
+        }
+    }
 
-        ring_idx += 1;
-        ring_idx %= 3;
+    // Send the columns at the end of the row, which no pixel of this row reaches past
+    for i in width - reach..width {
+        send_column(&values, i);
     }
+}
 
-}
\ No newline at end of file
+/// Replaces the values of a column of this row and the rows below with the values that the
+/// previous thread sent, which have the error of all rows above added to them.
+fn receive_column(values_recv: &Receiver<[f32; 3]>, values: &mut [Vec<[f32; 3]>], x: u32) {
+    let depth = values.len() - 1;
+    for row in &mut values[..depth] {
+        // The previous thread never ends before it has sent every column
+        row[x as usize] = values_recv.recv().unwrap();
+    }
+}
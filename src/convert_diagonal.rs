@@ -0,0 +1,114 @@
+use crate::colors::ColorSearch;
+use crate::convert::{
+    distribute_rgb_channels, Converter, DiffusionOptions, IndexedImage, ParallelConverter,
+    Parallelism, RowScan,
+};
+use image::RgbImage;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// A converter that converts the image one anti-diagonal at a time, and the pixels of each
+/// anti-diagonal in parallel.
+///
+/// The `i`th pixel in the scan of row `y` is on the diagonal `i + lag * y`. With a lag of one
+/// more than the kernel's row lag, every pixel whose error reaches a pixel is on an earlier
+/// diagonal, so the pixels of a diagonal don't depend on each other. For Floyd-Steinberg the lag
+/// is 2, which gives the "knight's move" diagonals `x + 2y`. Each pixel pulls the errors of the
+/// pixels before it in the same order as `SingleThreadedConverter` adds them, so the result is
+/// the same.
+///
+/// In a serpentine scan each row has to wait for all of the row above it, so there is only one
+/// pixel on each diagonal.
+pub struct DiagonalConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+    parallelism: Parallelism,
+}
+
+impl DiagonalConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        DiagonalConverter {
+            tree,
+            options: DiffusionOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ParallelConverter for DiagonalConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for DiagonalConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let mut indices = vec![0; (width * height) as usize];
+
+        // The diffused error of each pixel in row-major order
+        let mut errors = vec![[0.0_f32; 3]; (width * height) as usize];
+
+        if width > 0 && height > 0 {
+            let color_space = self.options.color_space;
+            let scan = RowScan::new(&self.options, width);
+            let taps: Vec<_> = (0..height).map(|y| scan.gather_taps(y)).collect();
+            let lag = if self.options.serpentine {
+                width
+            } else {
+                self.options.kernel.row_lag() + 1
+            };
+
+            self.parallelism.install(|| {
+                for diagonal in 0..(height - 1) * lag + width {
+                    // The rows that have a pixel on this diagonal, where 0 <= i < width
+                    let first_row = (diagonal + 1).saturating_sub(width).div_ceil(lag);
+                    let last_row = (diagonal / lag).min(height - 1);
+
+                    // Convert the pixels of the diagonal, which only read the errors of earlier diagonals
+                    let converted: Vec<(usize, u8, [f32; 3])> = (first_row..=last_row)
+                        .into_par_iter()
+                        .map(|y| {
+                            let x = scan.column(y, diagonal - lag * y);
+                            let mut values = color_space.decode(image.get_pixel(x, y));
+
+                            for &([dx, dy], factor) in &taps[y as usize] {
+                                let source_x = x as i32 - dx;
+                                if source_x < 0 || source_x as u32 >= width {
+                                    continue;
+                                }
+
+                                let source = (y - dy as u32) * width + source_x as u32;
+                                distribute_rgb_channels(
+                                    &mut values,
+                                    errors[source as usize],
+                                    factor,
+                                );
+                            }
+
+                            let (closest_color, _) =
+                                self.tree.find_closest(&color_space.encode(values));
+                            let errors = self
+                                .options
+                                .attenuate(color_space.error(values, &closest_color.rgb));
+                            ((y * width + x) as usize, closest_color.index, errors)
+                        })
+                        .collect();
+
+                    // Then store their results for the next diagonals
+                    for (index, palette_index, pixel_errors) in converted {
+                        indices[index] = palette_index;
+                        errors[index] = pixel_errors;
+                    }
+                }
+            });
+        }
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
@@ -0,0 +1,192 @@
+use crate::colors::ColorSearch;
+use crate::convert::{
+    attenuate, ColorSpace, Converter, IndexedImage, ParallelConverter, Parallelism,
+};
+use image::RgbImage;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// The width and height of the class matrix.
+const CLASS_SIZE: usize = 8;
+
+/// Knuth's class matrix, which is tiled over the image.
+/// Pixels are converted in the order of their class.
+const CLASS_MATRIX: [[u8; CLASS_SIZE]; CLASS_SIZE] = [
+    [34, 48, 40, 32, 29, 15, 23, 31],
+    [42, 58, 56, 53, 21, 5, 7, 10],
+    [50, 62, 61, 45, 13, 1, 2, 18],
+    [38, 46, 54, 37, 25, 17, 9, 26],
+    [28, 14, 22, 30, 35, 49, 41, 33],
+    [20, 4, 6, 11, 43, 59, 57, 52],
+    [12, 0, 3, 19, 51, 63, 60, 44],
+    [24, 16, 8, 27, 39, 47, 55, 36],
+];
+
+/// Options for `DotDiffusionConverter`.
+///
+/// The class matrix decides where the error goes and in which order the pixels are converted,
+/// so unlike `DiffusionOptions` there is no kernel or scan direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DotDiffusionOptions {
+    pub color_space: ColorSpace,
+    /// The factor of the error that is diffused, from 0.0 (no dithering) to 1.0 (all of it).
+    pub strength: f32,
+    /// Clamps each channel of the diffused error to `[-limit, limit]`, in working values
+    /// from 0.0 to 1.0.
+    pub error_limit: Option<f32>,
+}
+
+impl Default for DotDiffusionOptions {
+    fn default() -> Self {
+        DotDiffusionOptions {
+            color_space: ColorSpace::default(),
+            strength: 1.0,
+            error_limit: None,
+        }
+    }
+}
+
+impl DotDiffusionOptions {
+    /// Returns the part of the error of a pixel that is diffused, after the strength and limit.
+    pub fn attenuate(&self, errors: [f32; 3]) -> [f32; 3] {
+        attenuate(errors, self.strength, self.error_limit)
+    }
+}
+
+/// A converter that implements Knuth's dot diffusion.
+///
+/// The image is split into 8x8 cells, and the class matrix gives each pixel of a cell a class.
+/// The error of a pixel only goes to its neighbors with a higher class, which are converted later.
+/// Pixels of the same class are at least 8 pixels apart, so their neighbors never overlap
+/// and all pixels of a class are converted in parallel.
+pub struct DotDiffusionConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DotDiffusionOptions,
+    parallelism: Parallelism,
+}
+
+impl DotDiffusionConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        DotDiffusionConverter {
+            tree,
+            options: DotDiffusionOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DotDiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ParallelConverter for DotDiffusionConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for DotDiffusionConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let mut indices = vec![0; width * height];
+
+        // The working values of each pixel in row-major order, which the error is added to
+        let color_space = self.options.color_space;
+        let mut values: Vec<[f32; 3]> = image
+            .pixels()
+            .map(|pixel| color_space.decode(pixel))
+            .collect();
+
+        let mut positions = [(0, 0); CLASS_SIZE * CLASS_SIZE];
+        for (y, row) in CLASS_MATRIX.iter().enumerate() {
+            for (x, &class) in row.iter().enumerate() {
+                positions[class as usize] = (x, y);
+            }
+        }
+
+        let columns = width.div_ceil(CLASS_SIZE);
+        let cells = columns * height.div_ceil(CLASS_SIZE);
+        self.parallelism.install(|| {
+            for (x_in_cell, y_in_cell) in positions {
+                // Convert the pixels of this class in every cell, which only read their own value
+                let converted: Vec<(usize, usize, u8, [f32; 3])> = (0..cells)
+                    .into_par_iter()
+                    .filter_map(|cell| {
+                        let x = cell % columns * CLASS_SIZE + x_in_cell;
+                        let y = cell / columns * CLASS_SIZE + y_in_cell;
+                        if x >= width || y >= height {
+                            return None;
+                        }
+
+                        let pixel = values[y * width + x];
+                        let (closest_color, _) = self.tree.find_closest(&color_space.encode(pixel));
+                        let errors = self
+                            .options
+                            .attenuate(color_space.error(pixel, &closest_color.rgb));
+                        Some((x, y, closest_color.index, errors))
+                    })
+                    .collect();
+
+                // Then diffuse their errors to the neighbors that haven't been converted yet
+                for (x, y, index, errors) in converted {
+                    indices[y * width + x] = index;
+                    diffuse(&mut values, width, height, x, y, errors);
+                }
+            }
+        });
+
+        IndexedImage::new(
+            width as u32,
+            height as u32,
+            indices,
+            self.tree.palette().clone(),
+        )
+    }
+}
+
+/// Adds the error of the pixel at `x, y` to its neighbors with a higher class.
+/// Orthogonal neighbors get twice the weight of diagonal ones.
+fn diffuse(
+    values: &mut [[f32; 3]],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    errors: [f32; 3],
+) {
+    let class = class_at(x, y);
+    let mut neighbors = Vec::with_capacity(8);
+    for dy in -1..=1_i32 {
+        for dx in -1..=1_i32 {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+            if (dx, dy) == (0, 0)
+                || nx < 0
+                || ny < 0
+                || nx as usize >= width
+                || ny as usize >= height
+            {
+                continue;
+            }
+
+            let (nx, ny) = (nx as usize, ny as usize);
+            if class_at(nx, ny) > class {
+                let weight = if dx == 0 || dy == 0 { 2.0 } else { 1.0 };
+                neighbors.push((ny * width + nx, weight));
+            }
+        }
+    }
+
+    // A pixel without higher neighbors (a "baron") drops its error
+    let total: f32 = neighbors.iter().map(|(_, weight)| weight).sum();
+    for (index, weight) in neighbors {
+        for (value, error) in values[index].iter_mut().zip(errors) {
+            *value += error * weight / total;
+        }
+    }
+}
+
+fn class_at(x: usize, y: usize) -> u8 {
+    CLASS_MATRIX[y % CLASS_SIZE][x % CLASS_SIZE]
+}
@@ -1,103 +1,154 @@
 use std::cmp::Ordering;
-use std::ops::Deref;
-use crate::colors::get_color_tree;
-use crate::convert::{Converter, distribute_rgb_channels, DITHERING_MATRIX};
+use crate::colors::{ColorSearch, PaletteColor};
+use crate::convert::{
+    Converter, DiffusionOptions, distribute_rgb_channels, IndexedImage, ParallelConverter, Parallelism, RowScan,
+};
 use crossbeam::channel::{Receiver, Sender, unbounded};
-use image::{Rgb, RgbImage};
+use image::RgbImage;
 use rayon::Scope;
+use std::sync::atomic::{AtomicU8, Ordering as AtomicOrdering};
 use std::sync::{Arc, Mutex};
 
 /// A converter that implements the Floyd-Steinberg dithering algorithm using multiple threads.
-/// To access the pixels in a thread-safe manner, it represents the image as a vector of Arc<Mutex<Rgb<u8>>>.
-pub struct MutexConverter;
+/// To access the pixels in a thread-safe manner, it represents the working values of the image
+/// as a vector of Arc<Mutex<[f32; 3]>>.
+/// The palette index chosen for each pixel is stored in a separate vector of atomics.
+pub struct MutexConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+    parallelism: Parallelism,
+}
 
 impl MutexConverter {
-    pub fn new() -> Self {
-        MutexConverter {}
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        MutexConverter {
+            tree,
+            options: DiffusionOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ParallelConverter for MutexConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
     }
 }
 
 impl Converter for MutexConverter {
-    fn convert(&self, mut image: RgbImage) -> RgbImage {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
         let (width, height) = image.dimensions();
 
         // Thread safe image
-        let mut image_send: Vec<Arc<Mutex<Rgb<u8>>>> = Vec::with_capacity((width * height) as usize);
+        let color_space = self.options.color_space;
+        let mut image_send: Vec<Arc<Mutex<[f32; 3]>>> = Vec::with_capacity((width * height) as usize);
 
         // Initialize each pixel
         for i in 0..(image.height() * image.width()) {
             let x = i % image.width();
             let y = i / image.width();
 
-            image_send.push(Arc::new(Mutex::new(*image.get_pixel(x, y))));
+            image_send.push(Arc::new(Mutex::new(color_space.decode(image.get_pixel(x, y)))));
         }
 
         // Wrap in Arc
         let image_send = Arc::new(image_send);
+        let indices: Arc<Vec<AtomicU8>> =
+            Arc::new((0..width * height).map(|_| AtomicU8::new(0)).collect());
 
         // Convert the image
-        rayon::scope(|s| {
-            thread(
-                s,
-                None,
-                0,
-                width,
-                height,
-                image_send.clone(),
-            )
-        });
-
-        // Get rid of the mutexes and return the image
-        for y in 0..height {
-            for x in 0..width {
-                let index = (y * width + x) as usize;
-                let color = image_send[index].lock().unwrap();
-                image.put_pixel(x, y, *color);
-            }
+        let scan = RowScan::new(&self.options, width);
+        if height > 0 {
+            self.parallelism.install(|| {
+                rayon::scope(|s| {
+                    thread(
+                        s,
+                        &*self.tree,
+                        &self.options,
+                        &scan,
+                        None,
+                        0,
+                        width,
+                        height,
+                        image_send.clone(),
+                        indices.clone(),
+                    )
+                });
+            });
         }
 
-        image
+        // Get rid of the atomics and return the image
+        let indices = Arc::into_inner(indices)
+            .unwrap()
+            .into_iter()
+            .map(AtomicU8::into_inner)
+            .collect();
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
     }
 }
 
-fn thread(
-    s: &Scope,
+#[allow(clippy::too_many_arguments)]
+fn thread<'s>(
+    s: &Scope<'s>,
+    tree: &'s dyn ColorSearch,
+    options: &'s DiffusionOptions,
+    scan: &'s RowScan,
     ch: Option<Receiver<()>>,
     y: u32,
     width: u32,
     height: u32,
-    image: Arc<Vec<Arc<Mutex<Rgb<u8>>>>>,
+    image: Arc<Vec<Arc<Mutex<[f32; 3]>>>>,
+    indices: Arc<Vec<AtomicU8>>,
 ) {
+    let color_space = options.color_space;
+
+    // The next row is spawned once this row is far enough ahead that the rows add their error
+    // to each pixel in scan order, and after that every message means that this row has
+    // converted one more pixel.
+    // In a serpentine scan the next row starts where this row ends, so it has to wait for all of it
+    let spawn_at = if options.serpentine {
+        width.saturating_sub(1)
+    } else {
+        options.kernel.ordered_row_lag().min(width.saturating_sub(1))
+    };
+
     let mut sender: Option<Sender<()>> = None;
-    for x in 0..width {
+    for i in 0..width {
+        let x = scan.column(y, i);
         let index = (y * width + x) as usize;
 
-        // Block until message received, unless this is the first row
+        // Block until message received, unless this is the first row or the first pixel
         // This is to ensure that the threads are in sync
         if let Some(ch) = &ch {
-            // Don't care if it errors: just unblock
-            let _ = ch.recv();
+            if i > 0 {
+                // Don't care if it errors: the previous row is done
+                let _ = ch.recv();
+            }
         }
 
         // Scope to retrieve the pixel value
-        let closest_color: Rgb<u8>;
-        let difference: [i16; 3];
+        let closest_color: PaletteColor;
+        let errors: [f32; 3];
         {
-            let color = image[index].lock().unwrap();
-            (closest_color, difference) = get_color_tree().find_closest(color.deref());
-        }
+            let values = image[index].lock().unwrap();
+            (closest_color, _) = tree.find_closest(&color_space.encode(*values));
 
-        // Apply converted pixel
-        {
-            let mut pixel = image[index].lock().unwrap();
-            *pixel = closest_color;
+            // The error is the difference between the working values
+            // and the closest color in the palette, attenuated by the options
+            errors = options.attenuate(color_space.error(*values, &closest_color.rgb));
         }
 
-        // Normalize the error to the range [0, 1]
-        let errors = difference.map(|err| err as f32 / 256.0);
+        // Apply converted pixel
+        indices[index].store(closest_color.index, AtomicOrdering::Relaxed);
 
-        // Propagate errors to each of the four pixels according to Floyd-Steinberg
-        for ([vx, vy], factor) in DITHERING_MATRIX {
+        // Propagate errors to the pixels of the kernel
+        for &([vx, vy], factor) in scan.kernel(y).taps() {
             let x = x as i32 + vx;
             let y = y as i32 + vy;
 
@@ -116,7 +167,7 @@ fn thread(
         }
 
         if y < height - 1 {
-            match x.cmp(&1) {
+            match i.cmp(&spawn_at) {
                 // This whole block will only be triggered once but the borrow checker doesn't know
                 // So all the `move` shenanigans is to satisfy the borrow checker
                 Ordering::Equal => {
@@ -125,14 +176,19 @@ fn thread(
 
                     // Spawn the next thread with moved values
                     let cloned_image_ref = image.clone();
+                    let cloned_indices_ref = indices.clone();
                     s.spawn(move |s1| {
                         thread(
                             s1,
+                            tree,
+                            options,
+                            scan,
                             Some(receiver),
                             y + 1,
                             width,
                             height,
                             cloned_image_ref,
+                            cloned_indices_ref,
                         )
                     });
                 }
@@ -0,0 +1,53 @@
+use crate::colors::ColorSearch;
+use crate::convert::{Converter, IndexedImage, ParallelConverter, Parallelism};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// The number of pixels that each task converts.
+const CHUNK_SIZE: usize = 4096;
+
+/// A converter that maps every pixel to the closest color in the palette, without dithering.
+/// The pixels don't depend on each other, so chunks of them are converted in parallel.
+/// This is the baseline for the quality of the dithering converters,
+/// and the right choice for pixel art that shouldn't be dithered at all.
+pub struct NearestConverter {
+    tree: Arc<dyn ColorSearch>,
+    parallelism: Parallelism,
+}
+
+impl NearestConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        NearestConverter {
+            tree,
+            parallelism: Parallelism::default(),
+        }
+    }
+}
+
+impl ParallelConverter for NearestConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for NearestConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let mut indices = vec![0; (width * height) as usize];
+
+        self.parallelism.install(|| {
+            indices
+                .par_chunks_mut(CHUNK_SIZE)
+                .zip(image.as_raw().par_chunks(CHUNK_SIZE * 3))
+                .for_each(|(indices, pixels)| {
+                    for (index, pixel) in indices.iter_mut().zip(pixels.chunks_exact(3)) {
+                        let color = Rgb([pixel[0], pixel[1], pixel[2]]);
+                        *index = self.tree.find_closest(&color).0.index;
+                    }
+                });
+        });
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
@@ -0,0 +1,304 @@
+use crate::colors::{ColorSearch, PaletteColor};
+use crate::convert::{ColorSpace, Converter, IndexedImage, ParallelConverter, Parallelism};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::sync::{Arc, OnceLock};
+
+/// The width and height of the blue noise texture.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// The standard deviation of the Gaussian that void-and-cluster uses to find clusters and voids.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+static BLUE_NOISE: OnceLock<Vec<f32>> = OnceLock::new();
+
+/// The matrix of thresholds that is tiled over the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThresholdMap {
+    /// A Bayer matrix with the given width and height, which must be 2, 4, 8 or 16.
+    /// Gives the regular cross-hatch pattern of ordered dithering.
+    Bayer(u32),
+    /// A 64x64 blue noise texture, which has no visible pattern.
+    BlueNoise,
+}
+
+impl ThresholdMap {
+    /// Returns the width of the matrix and its thresholds in row-major order,
+    /// each in the range [0, 1).
+    ///
+    /// # Panics
+    /// Panics if the size of a Bayer matrix isn't supported.
+    fn thresholds(self) -> (usize, Vec<f32>) {
+        match self {
+            ThresholdMap::Bayer(size) => {
+                assert!(
+                    matches!(size, 2 | 4 | 8 | 16),
+                    "a Bayer matrix must be 2x2, 4x4, 8x8 or 16x16"
+                );
+                let size = size as usize;
+                let matrix = bayer(size);
+                let count = (size * size) as f32;
+                let thresholds = matrix
+                    .into_iter()
+                    .map(|rank| (rank as f32 + 0.5) / count)
+                    .collect();
+                (size, thresholds)
+            }
+            ThresholdMap::BlueNoise => (
+                BLUE_NOISE_SIZE,
+                BLUE_NOISE
+                    .get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE))
+                    .clone(),
+            ),
+        }
+    }
+}
+
+/// Options for `OrderedConverter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderedOptions {
+    pub threshold_map: ThresholdMap,
+    /// How much the pixels are dithered, from 0.0 (the closest color) to 1.0 (full mixing).
+    pub strength: f32,
+    /// The number of palette colors mixed for each pixel.
+    /// More candidates give finer mixes, but each one is a search in the palette.
+    pub candidates: usize,
+    /// The color space in which the error of the candidates builds up and their luminance is
+    /// compared. In sRGB, mixes of light and dark colors come out too dark.
+    pub color_space: ColorSpace,
+}
+
+impl Default for OrderedOptions {
+    fn default() -> Self {
+        OrderedOptions {
+            threshold_map: ThresholdMap::Bayer(8),
+            strength: 1.0,
+            candidates: 16,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+/// A converter that uses ordered dithering, where each pixel only depends on its own color
+/// and its position, so all rows are converted in parallel.
+///
+/// It works with any palette by mixing colors (Knoll's pattern dithering): for each pixel it
+/// picks candidate colors whose mix approximates the pixel's color, sorts them by luminance,
+/// and the threshold of the pixel's position picks one of them.
+pub struct OrderedConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: OrderedOptions,
+    parallelism: Parallelism,
+}
+
+impl OrderedConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        OrderedConverter {
+            tree,
+            options: OrderedOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: OrderedOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the candidate colors for a pixel, sorted by luminance.
+    fn candidates(&self, color: &Rgb<u8>) -> Vec<PaletteColor> {
+        let color_space = self.options.color_space;
+        let goal = color_space.decode(color);
+        let mut error = [0.0_f32; 3];
+        let mut candidates = Vec::with_capacity(self.options.candidates);
+
+        for _ in 0..self.options.candidates {
+            // Aim past the goal by the error of the candidates so far, so their mix gets closer to it
+            let attempt =
+                color_space.encode([0, 1, 2].map(|i| goal[i] + error[i] * self.options.strength));
+            let (candidate, _) = self.tree.find_closest(&attempt);
+            let candidate_error = color_space.error(goal, &candidate.rgb);
+            for (error, candidate_error) in error.iter_mut().zip(candidate_error) {
+                *error += candidate_error;
+            }
+            candidates.push(candidate);
+        }
+
+        let luminance = |color: &PaletteColor| luminance(color_space.decode(&color.rgb));
+        candidates.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+        candidates
+    }
+}
+
+impl ParallelConverter for OrderedConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for OrderedConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        assert!(
+            self.options.candidates > 0,
+            "at least one candidate color is needed"
+        );
+
+        let (width, height) = image.dimensions();
+        let (size, thresholds) = self.options.threshold_map.thresholds();
+        let mut indices = vec![0; (width * height) as usize];
+
+        self.parallelism.install(|| {
+            indices
+                .par_chunks_mut(width.max(1) as usize)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    for (x, index) in row.iter_mut().enumerate() {
+                        let candidates = self.candidates(image.get_pixel(x as u32, y as u32));
+                        let threshold = thresholds[(y % size) * size + x % size];
+                        let choice = ((threshold * candidates.len() as f32) as usize)
+                            .min(candidates.len() - 1);
+                        *index = candidates[choice].index;
+                    }
+                });
+        });
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
+
+/// Returns the Rec. 601 weighted sum of working values, which the candidates are sorted by.
+fn luminance(values: [f32; 3]) -> f32 {
+    0.299 * values[0] + 0.587 * values[1] + 0.114 * values[2]
+}
+
+/// Returns the Bayer matrix of the given size in row-major order, with the ranks 0 to size² - 1.
+fn bayer(size: usize) -> Vec<u32> {
+    if size == 1 {
+        return vec![0];
+    }
+
+    // Each quadrant is the smaller matrix, scaled and offset in the order of the 2x2 matrix
+    let half = size / 2;
+    let smaller = bayer(half);
+    let mut matrix = vec![0; size * size];
+    for y in 0..size {
+        for x in 0..size {
+            let offset = [[0, 2], [3, 1]][y / half][x / half];
+            matrix[y * size + x] = 4 * smaller[(y % half) * half + x % half] + offset;
+        }
+    }
+    matrix
+}
+
+/// A binary pattern on a torus and the Gaussian-weighted density of its set pixels around each pixel.
+#[derive(Clone)]
+struct NoisePattern {
+    size: usize,
+    set: Vec<bool>,
+    energy: Vec<f32>,
+    weights: Vec<f32>,
+}
+
+impl NoisePattern {
+    fn new(size: usize) -> Self {
+        // The weight of a set pixel at each offset, wrapping around the edges
+        let weights = (0..size * size)
+            .map(|i| {
+                let dx = (i % size).min(size - i % size) as f32;
+                let dy = (i / size).min(size - i / size) as f32;
+                (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect();
+
+        NoisePattern {
+            size,
+            set: vec![false; size * size],
+            energy: vec![0.0; size * size],
+            weights,
+        }
+    }
+
+    fn toggle(&mut self, pixel: usize) {
+        self.set[pixel] = !self.set[pixel];
+        let sign = if self.set[pixel] { 1.0 } else { -1.0 };
+
+        let size = self.size;
+        let (px, py) = (pixel % size, pixel / size);
+        for (i, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *energy += sign * self.weights[dy * size + dx];
+        }
+    }
+
+    /// Returns the set pixel with the most set pixels around it.
+    fn tightest_cluster(&self) -> usize {
+        (0..self.set.len())
+            .filter(|&i| self.set[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+
+    /// Returns the unset pixel with the fewest set pixels around it.
+    fn largest_void(&self) -> usize {
+        (0..self.set.len())
+            .filter(|&i| !self.set[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+}
+
+/// Generates a blue noise threshold texture with Ulichney's void-and-cluster method.
+/// Returns the thresholds in row-major order, each in the range [0, 1).
+fn void_and_cluster(size: usize) -> Vec<f32> {
+    let count = size * size;
+    let mut pattern = NoisePattern::new(size);
+
+    // Start with a tenth of the pixels set at random, with a fixed seed so the texture is always the same
+    let mut state = 0x2545_F491_4F6C_DD1D_u64;
+    let mut initial = 0;
+    while initial < count / 10 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let pixel = (state % count as u64) as usize;
+        if !pattern.set[pixel] {
+            pattern.toggle(pixel);
+            initial += 1;
+        }
+    }
+
+    // Spread the set pixels evenly by moving the tightest cluster into the largest void
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        if void == cluster {
+            pattern.toggle(cluster);
+            break;
+        }
+        pattern.toggle(void);
+    }
+
+    let mut ranks = vec![0; count];
+
+    // Rank the initial pixels by removing the tightest cluster until none are left
+    let mut removed = pattern.clone();
+    for rank in (0..initial).rev() {
+        let cluster = removed.tightest_cluster();
+        removed.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+
+    // Rank the other pixels by filling the largest void until every pixel is set
+    for rank in initial..count {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / count as f32)
+        .collect()
+}
@@ -0,0 +1,100 @@
+use crate::colors::ColorSearch;
+use crate::convert::{Converter, DiffusionOptions, IndexedImage, ParallelConverter, Parallelism};
+use crate::convert_wavefront::Wavefront;
+use image::RgbImage;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A converter that converts rows at the same time on a fixed number of workers.
+///
+/// `MutexConverter`, `ChannelConverter` and `WavefrontConverter` spawn a task for every row,
+/// which blocks a thread of the pool while it waits for the row above it. Here each of the `N`
+/// workers claims the next row that nobody has started when it's done with its last one, so the
+/// rows are pipelined the same way but there are never more than `N` tasks. Since a row is only
+/// claimed by a worker that is already running, the row it waits for is always being converted,
+/// even when some workers are stuck in the queue of a busy pool. The rows are synchronized like
+/// in `WavefrontConverter`, and the result is the same.
+pub struct PoolConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+    workers: Option<usize>,
+    parallelism: Parallelism,
+}
+
+impl PoolConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        PoolConverter {
+            tree,
+            options: DiffusionOptions::default(),
+            workers: None,
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets the maximum number of workers. By default there is one for every thread of the pool
+    /// it runs on.
+    ///
+    /// The number of workers is capped at the number of threads of that pool, and at the number
+    /// of rows, so `with_workers(8)` on a pool with 4 threads runs 4 workers. A worker spins on
+    /// its thread while it waits for the row above, so a worker without a thread of its own would
+    /// only start once another one has run out of rows.
+    ///
+    /// # Panics
+    /// Panics if `workers` is 0.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        assert!(workers > 0, "at least one worker is needed");
+        self.workers = Some(workers);
+        self
+    }
+}
+
+impl ParallelConverter for PoolConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for PoolConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let mut indices = vec![0; (width * height) as usize];
+
+        if width > 0 && height > 0 {
+            let wavefront = Wavefront::new(&*self.tree, &self.options, &image);
+            self.parallelism.install(|| {
+                let threads = rayon::current_num_threads();
+                let workers = self
+                    .workers
+                    .unwrap_or(threads)
+                    .min(threads)
+                    .min(height as usize);
+
+                // The workers claim the rows in order, so the rows above a claimed row are
+                // already claimed by running workers
+                let rows: Vec<Mutex<&mut [u8]>> =
+                    indices.chunks_mut(width as usize).map(Mutex::new).collect();
+                let next_row = AtomicU32::new(0);
+
+                rayon::scope(|s| {
+                    for _ in 0..workers {
+                        s.spawn(|_| loop {
+                            let y = next_row.fetch_add(1, Ordering::Relaxed);
+                            if y >= height {
+                                break;
+                            }
+                            let mut row = rows[y as usize].lock().unwrap();
+                            wavefront.convert_row(y, &mut row, |_| {});
+                        });
+                    }
+                });
+            });
+        }
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
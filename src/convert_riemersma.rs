@@ -0,0 +1,163 @@
+use crate::colors::ColorSearch;
+use crate::convert::{ColorSpace, Converter, IndexedImage};
+use image::RgbImage;
+use std::sync::Arc;
+
+/// Options for `RiemersmaConverter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiemersmaOptions {
+    /// The number of previous pixels whose error is added to the current pixel.
+    pub history: usize,
+    /// The weight of the error of the oldest pixel in the history relative to the newest one.
+    /// The weights in between fall off exponentially.
+    pub ratio: f32,
+    /// The color space in which the errors are measured and added.
+    pub color_space: ColorSpace,
+}
+
+impl Default for RiemersmaOptions {
+    fn default() -> Self {
+        RiemersmaOptions {
+            history: 16,
+            ratio: 1.0 / 16.0,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+/// A converter that implements Riemersma's dithering algorithm: it walks the image along a
+/// Hilbert curve and adds the errors of the last pixels on the curve to each pixel,
+/// weighted so that recent errors count the most.
+/// Since the curve keeps changing direction, there are no directional artifacts.
+pub struct RiemersmaConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: RiemersmaOptions,
+}
+
+impl RiemersmaConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        RiemersmaConverter {
+            tree,
+            options: RiemersmaOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: RiemersmaOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl Converter for RiemersmaConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        assert!(
+            self.options.history > 0,
+            "the history must have at least one pixel"
+        );
+
+        let (width, height) = image.dimensions();
+        let mut indices = vec![0; (width * height) as usize];
+
+        // weights[0] is for the oldest error and weights[history - 1] for the newest
+        let history = self.options.history;
+        let weights: Vec<f32> = (0..history)
+            .map(|i| {
+                let age = (history - 1 - i) as f32 / (history - 1).max(1) as f32;
+                self.options.ratio.powf(age)
+            })
+            .collect();
+
+        // A ring buffer of the errors, where `next` is the position of the oldest one
+        let mut errors = vec![[0.0_f32; 3]; history];
+        let mut next = 0;
+
+        let color_space = self.options.color_space;
+        hilbert_curve(width, height, &mut |x, y| {
+            let original = color_space.decode(image.get_pixel(x, y));
+
+            let mut values = original;
+            for (i, weight) in weights.iter().enumerate() {
+                let error = errors[(next + i) % history];
+                for (value, error) in values.iter_mut().zip(error) {
+                    *value += error * weight;
+                }
+            }
+
+            let (closest_color, _) = self.tree.find_closest(&color_space.encode(values));
+            indices[(y * width + x) as usize] = closest_color.index;
+
+            // The error is measured from the original color, not the one with the error added
+            errors[next] = color_space.error(original, &closest_color.rgb);
+            next = (next + 1) % history;
+        });
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
+
+/// Calls `visit` for each pixel of the image in the order of a generalized Hilbert curve,
+/// which covers rectangles of any size. Every step goes to a neighboring pixel, with at most
+/// one diagonal step when the width or height is odd.
+fn hilbert_curve(width: u32, height: u32, visit: &mut impl FnMut(u32, u32)) {
+    let (width, height) = (width as i64, height as i64);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // The curve starts along the longer side
+    if width >= height {
+        hilbert_segment(0, 0, [width, 0], [0, height], visit);
+    } else {
+        hilbert_segment(0, 0, [0, height], [width, 0], visit);
+    }
+}
+
+/// Visits the rectangle at `x, y` that spans `a` in the main direction of the curve
+/// and `b` in the orthogonal direction, by splitting it into smaller curves.
+fn hilbert_segment(x: i64, y: i64, a: [i64; 2], b: [i64; 2], visit: &mut impl FnMut(u32, u32)) {
+    let w = (a[0] + a[1]).abs();
+    let h = (b[0] + b[1]).abs();
+    let da = a.map(i64::signum);
+    let db = b.map(i64::signum);
+
+    // A single row or column is walked straight
+    if h == 1 || w == 1 {
+        let (step, length) = if h == 1 { (da, w) } else { (db, h) };
+        let (mut x, mut y) = (x, y);
+        for _ in 0..length {
+            visit(x as u32, y as u32);
+            x += step[0];
+            y += step[1];
+        }
+        return;
+    }
+
+    let mut a2 = a.map(|value| value.div_euclid(2));
+    let mut b2 = b.map(|value| value.div_euclid(2));
+    let w2 = (a2[0] + a2[1]).abs();
+    let h2 = (b2[0] + b2[1]).abs();
+
+    if 2 * w > 3 * h {
+        // Long rectangles are split in two along the main direction,
+        // with an even length for the first half so the curve can turn around
+        if w2 % 2 == 1 && w > 2 {
+            a2 = [a2[0] + da[0], a2[1] + da[1]];
+        }
+        hilbert_segment(x, y, a2, b, visit);
+        hilbert_segment(x + a2[0], y + a2[1], [a[0] - a2[0], a[1] - a2[1]], b, visit);
+    } else {
+        // Otherwise the rectangle is split into three: up, along, and back down
+        if h2 % 2 == 1 && h > 2 {
+            b2 = [b2[0] + db[0], b2[1] + db[1]];
+        }
+        hilbert_segment(x, y, b2, a2, visit);
+        hilbert_segment(x + b2[0], y + b2[1], a, [b[0] - b2[0], b[1] - b2[1]], visit);
+        hilbert_segment(
+            x + (a[0] - da[0]) + (b2[0] - db[0]),
+            y + (a[1] - da[1]) + (b2[1] - db[1]),
+            [-b2[0], -b2[1]],
+            [-(a[0] - a2[0]), -(a[1] - a2[1])],
+            visit,
+        );
+    }
+}
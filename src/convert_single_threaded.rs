@@ -1,35 +1,62 @@
-use crate::colors::get_color_tree;
-use crate::convert::{Converter, distribute_rgb_channels, DITHERING_MATRIX};
+use crate::colors::ColorSearch;
+use crate::convert::{Converter, DiffusionOptions, distribute_rgb_channels, IndexedImage, RowScan};
 use image::RgbImage;
+use std::sync::Arc;
 
 /// The standard single-threaded converter that implements the Floyd-Steinberg dithering algorithm.
-pub struct SingleThreadedConverter;
+/// It converts the pixels in the reference order of `DiffusionOptions`.
+pub struct SingleThreadedConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+}
 
 impl SingleThreadedConverter {
-    pub fn new() -> Self {
-        SingleThreadedConverter
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        SingleThreadedConverter {
+            tree,
+            options: DiffusionOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
     }
 }
 
 impl Converter for SingleThreadedConverter {
-    fn convert(&self, mut image: RgbImage) -> RgbImage {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
         let (width, height) = image.dimensions();
+        let color_space = self.options.color_space;
+        let scan = RowScan::new(&self.options, width);
+        let mut indices = vec![0; (width * height) as usize];
+
+        // The working values of each pixel in row-major order, which the error is added to
+        let mut values: Vec<[f32; 3]> = image
+            .pixels()
+            .map(|pixel| color_space.decode(pixel))
+            .collect();
 
-        for x in 0..width {
-            for y in 0..height {
-                let color = image.get_pixel(x, y);
+        // Row by row, so every pixel has the error of all the pixels whose kernel reaches it
+        // before it is converted
+        for y in 0..height {
+            for i in 0..width {
+                let x = scan.column(y, i);
+                let index = (y * width + x) as usize;
+                let color = color_space.encode(values[index]);
 
-                // Difference is the vector difference between the target color
-                // and the closest color in the palette expressed in RGB space
-                let (closest_color, difference) = get_color_tree().find_closest(color);
+                let (closest_color, _) = self.tree.find_closest(&color);
 
-                *image.get_pixel_mut(x, y) = closest_color;
+                indices[index] = closest_color.index;
 
-                // Normalize the error to the range [0, 1]
-                let errors = difference.map(|err| err as f32 / 256.0);
+                // The error is the difference between the working values
+                // and the closest color in the palette, attenuated by the options
+                let errors = self
+                    .options
+                    .attenuate(color_space.error(values[index], &closest_color.rgb));
 
-                // Propagate errors to each of the four pixels according to Floyd-Steinberg
-                for ([vx, vy], factor) in DITHERING_MATRIX {
+                // Propagate errors to the pixels of the kernel
+                for &([vx, vy], factor) in scan.kernel(y).taps() {
                     let x = x as i32 + vx;
                     let y = y as i32 + vy;
 
@@ -38,11 +65,11 @@ impl Converter for SingleThreadedConverter {
                         continue;
                     }
 
-                    let original_color = image.get_pixel_mut(x as u32, y as u32);
+                    let original_color = &mut values[(y as u32 * width + x as u32) as usize];
                     distribute_rgb_channels(original_color, errors, factor);
                 }
             }
         }
-        image
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
     }
 }
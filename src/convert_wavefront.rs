@@ -0,0 +1,178 @@
+use crate::colors::ColorSearch;
+use crate::convert::{
+    distribute_rgb_channels, Converter, DiffusionOptions, IndexedImage, ParallelConverter,
+    Parallelism, RowScan,
+};
+use crossbeam::utils::Backoff;
+use image::RgbImage;
+use rayon::Scope;
+use std::slice::ChunksMut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// A converter that converts rows at the same time like `MutexConverter`, but without locks.
+///
+/// Instead of pushing its error to the pixels after it, each pixel pulls the errors of the
+/// converted pixels before it, so a row only ever writes to its own slice of the palette indices
+/// and to its own part of one contiguous error buffer. Each row counts its converted pixels in an
+/// atomic counter, and a pixel waits on the counters of the rows above it until the pixels it
+/// pulls from are done.
+pub struct WavefrontConverter {
+    tree: Arc<dyn ColorSearch>,
+    options: DiffusionOptions,
+    parallelism: Parallelism,
+}
+
+impl WavefrontConverter {
+    pub fn new(tree: Arc<dyn ColorSearch>) -> Self {
+        WavefrontConverter {
+            tree,
+            options: DiffusionOptions::default(),
+            parallelism: Parallelism::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DiffusionOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ParallelConverter for WavefrontConverter {
+    fn parallelism_mut(&mut self) -> &mut Parallelism {
+        &mut self.parallelism
+    }
+}
+
+impl Converter for WavefrontConverter {
+    fn convert(&self, image: RgbImage) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let mut indices = vec![0; (width * height) as usize];
+
+        if width > 0 && height > 0 {
+            let wavefront = Wavefront::new(&*self.tree, &self.options, &image);
+            let mut rows = indices.chunks_mut(width as usize);
+            let first_row = rows.next().unwrap();
+            self.parallelism
+                .install(|| rayon::scope(|s| spawn_row(s, &wavefront, 0, first_row, rows)));
+        }
+
+        IndexedImage::new(width, height, indices, self.tree.palette().clone())
+    }
+}
+
+/// Converts row `y` and spawns the next row once this one is far enough ahead for its first pixel.
+fn spawn_row<'s>(
+    s: &Scope<'s>,
+    wavefront: &'s Wavefront,
+    y: u32,
+    indices: &'s mut [u8],
+    mut rest: ChunksMut<'s, u8>,
+) {
+    // In a serpentine scan the next row starts where this row ends, so it has to wait for all of it
+    let last = wavefront.width - 1;
+    let spawn_at = if wavefront.options.serpentine {
+        last
+    } else {
+        wavefront.options.kernel.row_lag().min(last)
+    };
+    let mut next_row = rest.next().map(|next_indices| (next_indices, rest));
+
+    wavefront.convert_row(y, indices, |i| {
+        if i == spawn_at {
+            if let Some((next_indices, rest)) = next_row.take() {
+                s.spawn(move |s| spawn_row(s, wavefront, y + 1, next_indices, rest));
+            }
+        }
+    });
+}
+
+/// The state that the rows share while they are converted. Each row is converted by
+/// `convert_row`, which waits for the rows above it, so rows can be converted on any threads
+/// as long as every row before a row is being converted or done.
+pub(crate) struct Wavefront<'a> {
+    tree: &'a dyn ColorSearch,
+    options: &'a DiffusionOptions,
+    scan: RowScan,
+    image: &'a RgbImage,
+    /// The diffused error of each pixel, as the bits of an f32 for each channel.
+    /// Only the row of a pixel writes its error, before it counts the pixel as done.
+    errors: Vec<AtomicU32>,
+    /// The number of pixels of each row that have been converted.
+    done: Vec<AtomicU32>,
+    width: u32,
+}
+
+impl<'a> Wavefront<'a> {
+    pub(crate) fn new(
+        tree: &'a dyn ColorSearch,
+        options: &'a DiffusionOptions,
+        image: &'a RgbImage,
+    ) -> Self {
+        let (width, height) = image.dimensions();
+        Wavefront {
+            tree,
+            options,
+            scan: RowScan::new(options, width),
+            image,
+            errors: (0..width * height * 3).map(|_| AtomicU32::new(0)).collect(),
+            done: (0..height).map(|_| AtomicU32::new(0)).collect(),
+            width,
+        }
+    }
+
+    /// Converts row `y` into its palette `indices`, and calls `converted` with the position
+    /// in the scan of the row after each pixel.
+    pub(crate) fn convert_row(&self, y: u32, indices: &mut [u8], mut converted: impl FnMut(u32)) {
+        let width = self.width;
+        let color_space = self.options.color_space;
+        let taps = self.scan.gather_taps(y);
+
+        for i in 0..width {
+            let x = self.scan.column(y, i);
+            let mut values = color_space.decode(self.image.get_pixel(x, y));
+
+            // Pull the error from the pixels whose kernel reaches this one
+            for &([dx, dy], factor) in &taps {
+                let source_x = x as i32 - dx;
+                if source_x < 0 || source_x as u32 >= width {
+                    continue;
+                }
+
+                let (source_x, source_y) = (source_x as u32, y - dy as u32);
+                if dy > 0 {
+                    // `column` is its own inverse, so this is the position of the pixel in its row
+                    self.wait_for(source_y, self.scan.column(source_y, source_x) + 1);
+                }
+                distribute_rgb_channels(&mut values, self.error(source_x, source_y), factor);
+            }
+
+            let (closest_color, _) = self.tree.find_closest(&color_space.encode(values));
+            indices[x as usize] = closest_color.index;
+
+            let errors = self
+                .options
+                .attenuate(color_space.error(values, &closest_color.rgb));
+            let index = ((y * width + x) * 3) as usize;
+            for (atomic, error) in self.errors[index..index + 3].iter().zip(errors) {
+                atomic.store(error.to_bits(), Ordering::Relaxed);
+            }
+            self.done[y as usize].store(i + 1, Ordering::Release);
+
+            converted(i);
+        }
+    }
+
+    /// Blocks until at least `count` pixels of row `y` have been converted.
+    fn wait_for(&self, y: u32, count: u32) {
+        let backoff = Backoff::new();
+        while self.done[y as usize].load(Ordering::Acquire) < count {
+            backoff.snooze();
+        }
+    }
+
+    fn error(&self, x: u32, y: u32) -> [f32; 3] {
+        let index = ((y * self.width + x) * 3) as usize;
+        [0, 1, 2].map(|i| f32::from_bits(self.errors[index + i].load(Ordering::Relaxed)))
+    }
+}
@@ -0,0 +1,17 @@
+pub mod color_lut;
+pub mod color_space;
+pub mod colors;
+pub mod convert;
+pub mod convert_channels;
+pub mod convert_diagonal;
+pub mod convert_dot;
+pub mod convert_mutex;
+pub mod convert_nearest;
+pub mod convert_ordered;
+pub mod convert_pool;
+pub mod convert_riemersma;
+pub mod convert_single_threaded;
+pub mod convert_wavefront;
+pub mod map_export;
+pub mod palette_files;
+pub mod palette_gen;
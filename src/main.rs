@@ -1,56 +1,108 @@
-use crate::colors::get_color_tree;
-use crate::convert::Converter;
-use crate::convert_single_threaded::SingleThreadedConverter;
-
-mod colors;
-mod convert;
-mod convert_mutex;
-mod convert_single_threaded;
-mod convert_channels;
+use floyd_steinberg_parallel_test::color_lut::ColorLut;
+use floyd_steinberg_parallel_test::colors::{ColorSearch, ColorTree, Palette};
+use floyd_steinberg_parallel_test::convert::Converter;
+use floyd_steinberg_parallel_test::convert_channels::ChannelConverter;
+use floyd_steinberg_parallel_test::convert_diagonal::DiagonalConverter;
+use floyd_steinberg_parallel_test::convert_dot::DotDiffusionConverter;
+use floyd_steinberg_parallel_test::convert_mutex::MutexConverter;
+use floyd_steinberg_parallel_test::convert_nearest::NearestConverter;
+use floyd_steinberg_parallel_test::convert_ordered::OrderedConverter;
+use floyd_steinberg_parallel_test::convert_pool::PoolConverter;
+use floyd_steinberg_parallel_test::convert_riemersma::RiemersmaConverter;
+use floyd_steinberg_parallel_test::convert_single_threaded::SingleThreadedConverter;
+use floyd_steinberg_parallel_test::convert_wavefront::WavefrontConverter;
+use std::sync::Arc;
 
 const TEST_FILES: [&str; 3] = ["700x980.jpg", "1920x1000.png", "4128x6192.jpg"];
 
 struct TestCase {
     name: &'static str,
-    converter: Box<dyn Converter>,
+    converter: fn(Arc<dyn ColorSearch>) -> Box<dyn Converter>,
 }
 
 fn main() -> anyhow::Result<()> {
     // Init the kd-tree
-    get_color_tree();
-
-    let mut test_cases: Vec<TestCase> = Vec::new();
-    test_cases.push(TestCase {
-        name: "single-threaded",
-        converter: Box::new(SingleThreadedConverter::new()),
-    });
-    test_cases.push(TestCase {
-        name: "mutex",
-        converter: Box::new(convert_mutex::MutexConverter::new()),
-    });
-    test_cases.push(TestCase {
-        name: "channels",
-        converter: Box::new(convert_channels::ChannelConverter::new()),
-    });
-
-    for case in test_cases.iter() {
-        println!("Running test case: {}", case.name);
-        for file in TEST_FILES.iter() {
-            print!("loading file: {}... ", file);
-            let image = image::open(format!("./test_images/{file}"))?.to_rgb8();
-
-            // Start time measurement
-            let start = std::time::Instant::now();
-            let result = case.converter.convert(image);
-
-            // End time measurement
-            let duration = start.elapsed();
-            println!("time elapsed: {:?}", duration);
-
-            // Save the converted image
-            result.save(format!(
-                "./test_images/converted_{}_{}.png", case.name, file
-            ))?;
+    let palette = Arc::new(Palette::minecraft());
+    let tree = Arc::new(ColorTree::new(palette.clone()));
+
+    let test_cases = [
+        TestCase {
+            name: "nearest",
+            converter: |search| Box::new(NearestConverter::new(search)),
+        },
+        TestCase {
+            name: "single-threaded",
+            converter: |search| Box::new(SingleThreadedConverter::new(search)),
+        },
+        TestCase {
+            name: "mutex",
+            converter: |search| Box::new(MutexConverter::new(search)),
+        },
+        TestCase {
+            name: "channels",
+            converter: |search| Box::new(ChannelConverter::new(search)),
+        },
+        TestCase {
+            name: "ordered",
+            converter: |search| Box::new(OrderedConverter::new(search)),
+        },
+        TestCase {
+            name: "riemersma",
+            converter: |search| Box::new(RiemersmaConverter::new(search)),
+        },
+        TestCase {
+            name: "dot-diffusion",
+            converter: |search| Box::new(DotDiffusionConverter::new(search)),
+        },
+        TestCase {
+            name: "wavefront",
+            converter: |search| Box::new(WavefrontConverter::new(search)),
+        },
+        TestCase {
+            name: "pool",
+            converter: |search| Box::new(PoolConverter::new(search)),
+        },
+        TestCase {
+            name: "pool-2",
+            converter: |search| Box::new(PoolConverter::new(search).with_workers(2)),
+        },
+        TestCase {
+            name: "diagonal",
+            converter: |search| Box::new(DiagonalConverter::new(search)),
+        },
+    ];
+
+    // The cases run with the kd-tree, and then again with a lookup table in front of it
+    for lut in [false, true] {
+        for case in test_cases.iter() {
+            let name = if lut { format!("{}-lut", case.name) } else { case.name.to_string() };
+            println!("Running test case: {}", name);
+            for file in TEST_FILES.iter() {
+                print!("loading file: {}... ", file);
+                let image = image::open(format!("./test_images/{file}"))?.to_rgb8();
+
+                // Every conversion gets a new table, which is dropped afterwards, so each one
+                // starts empty and the time includes filling it
+                let search: Arc<dyn ColorSearch> = if lut {
+                    Arc::new(ColorLut::new(ColorTree::new(palette.clone())))
+                } else {
+                    tree.clone()
+                };
+                let converter = (case.converter)(search);
+
+                // Start time measurement
+                let start = std::time::Instant::now();
+                let result = converter.convert(image);
+
+                // End time measurement
+                let duration = start.elapsed();
+                println!("time elapsed: {:?}", duration);
+
+                // Save the converted image
+                result.to_rgb_image().save(format!(
+                    "./test_images/converted_{}_{}.png", name, file
+                ))?;
+            }
         }
     }
 
@@ -0,0 +1,364 @@
+use crate::convert::IndexedImage;
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The width and height of a Minecraft map in pixels.
+pub const MAP_SIZE: u32 = 128;
+
+/// The data version of Minecraft 1.21.
+const DEFAULT_DATA_VERSION: i32 = 3953;
+
+// NBT tag types
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_INT: u8 = 3;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+
+/// Writes an image converted with a Minecraft palette as `map_<id>.dat` files.
+///
+/// The image is split into 128x128 tiles in row-major order, so the tile in column `x` and row `y`
+/// gets the ID `start_id + y * columns + x`. Pixels past the edge of the image are transparent.
+/// The palette indices of the image must be Minecraft map color IDs, e.g. from `Palette::minecraft`.
+pub struct MapExporter {
+    /// The ID of the top-left map.
+    pub start_id: u32,
+    /// The dimension the maps are in, e.g. `minecraft:overworld`.
+    pub dimension: String,
+    /// The world coordinates of the center of the maps.
+    pub x_center: i32,
+    pub z_center: i32,
+    /// Locked maps are never updated by the game, which keeps the image intact.
+    pub locked: bool,
+    /// The data version written to the files, identifying the Minecraft version.
+    pub data_version: i32,
+}
+
+impl MapExporter {
+    pub fn new(start_id: u32) -> Self {
+        MapExporter {
+            start_id,
+            dimension: "minecraft:overworld".to_string(),
+            x_center: 0,
+            z_center: 0,
+            locked: true,
+            data_version: DEFAULT_DATA_VERSION,
+        }
+    }
+
+    /// Returns the number of map columns and rows needed to cover the image.
+    pub fn tile_count(&self, image: &IndexedImage) -> (u32, u32) {
+        (
+            image.width().div_ceil(MAP_SIZE),
+            image.height().div_ceil(MAP_SIZE),
+        )
+    }
+
+    /// Writes one map file per tile to `dir`, which would usually be the `data` folder of a world.
+    /// Returns the paths of the written files in order of their IDs.
+    /// Fails without writing anything if the IDs of the tiles don't fit in a `u32`.
+    pub fn export(
+        &self,
+        image: &IndexedImage,
+        dir: impl AsRef<Path>,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let (columns, rows) = self.tile_count(image);
+        let tiles = columns * rows;
+        if tiles > 0 && self.start_id.checked_add(tiles - 1).is_none() {
+            anyhow::bail!(
+                "{tiles} maps starting at ID {} would go past the largest ID",
+                self.start_id
+            );
+        }
+        let mut paths = Vec::with_capacity(tiles as usize);
+
+        for tile_y in 0..rows {
+            for tile_x in 0..columns {
+                // Can't overflow, since the last ID was checked above
+                let id = self.start_id + tile_y * columns + tile_x;
+                let path = dir.join(format!("map_{id}.dat"));
+                let colors = tile_colors(image, tile_x, tile_y);
+
+                self.write_map(&path, &colors)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+                paths.push(path);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Writes `idcounts.dat` to `dir` so the game hands out IDs after `last_id` for new maps,
+    /// instead of overwriting the exported ones.
+    pub fn write_id_counts(&self, dir: impl AsRef<Path>, last_id: u32) -> anyhow::Result<()> {
+        let path = dir.as_ref().join("idcounts.dat");
+        write_gzip_nbt(&path, |w| {
+            write_tag_header(w, TAG_COMPOUND, "data")?;
+            write_tag_header(w, TAG_INT, "map")?;
+            w.write_all(&(last_id as i32).to_be_bytes())?;
+            w.write_all(&[TAG_END])?;
+
+            write_tag_header(w, TAG_INT, "DataVersion")?;
+            w.write_all(&self.data_version.to_be_bytes())
+        })
+        .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn write_map(&self, path: &Path, colors: &[u8]) -> std::io::Result<()> {
+        write_gzip_nbt(path, |w| {
+            write_tag_header(w, TAG_COMPOUND, "data")?;
+
+            write_tag_header(w, TAG_BYTE, "scale")?;
+            w.write_all(&[0])?;
+            write_tag_header(w, TAG_STRING, "dimension")?;
+            write_string(w, &self.dimension)?;
+            write_tag_header(w, TAG_BYTE, "trackingPosition")?;
+            w.write_all(&[0])?;
+            write_tag_header(w, TAG_BYTE, "unlimitedTracking")?;
+            w.write_all(&[0])?;
+            write_tag_header(w, TAG_BYTE, "locked")?;
+            w.write_all(&[self.locked as u8])?;
+            write_tag_header(w, TAG_INT, "xCenter")?;
+            w.write_all(&self.x_center.to_be_bytes())?;
+            write_tag_header(w, TAG_INT, "zCenter")?;
+            w.write_all(&self.z_center.to_be_bytes())?;
+
+            // Empty lists have the element type TAG_End
+            for name in ["banners", "frames"] {
+                write_tag_header(w, TAG_LIST, name)?;
+                w.write_all(&[TAG_END])?;
+                w.write_all(&0_i32.to_be_bytes())?;
+            }
+
+            write_tag_header(w, TAG_BYTE_ARRAY, "colors")?;
+            w.write_all(&(colors.len() as i32).to_be_bytes())?;
+            w.write_all(colors)?;
+
+            w.write_all(&[TAG_END])?;
+
+            write_tag_header(w, TAG_INT, "DataVersion")?;
+            w.write_all(&self.data_version.to_be_bytes())
+        })
+    }
+}
+
+/// Returns the map colors of one tile in row-major order.
+fn tile_colors(image: &IndexedImage, tile_x: u32, tile_y: u32) -> Vec<u8> {
+    let mut colors = vec![0; (MAP_SIZE * MAP_SIZE) as usize];
+
+    for y in 0..MAP_SIZE {
+        let image_y = tile_y * MAP_SIZE + y;
+        if image_y >= image.height() {
+            break;
+        }
+
+        for x in 0..MAP_SIZE {
+            let image_x = tile_x * MAP_SIZE + x;
+            if image_x >= image.width() {
+                break;
+            }
+            colors[(y * MAP_SIZE + x) as usize] = image.get_index(image_x, image_y);
+        }
+    }
+
+    colors
+}
+
+/// Writes a gzip-compressed NBT file with an unnamed root compound.
+/// `write_contents` writes the tags inside the root compound.
+fn write_gzip_nbt(
+    path: &Path,
+    write_contents: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = GzEncoder::new(file, Compression::default());
+
+    write_tag_header(&mut encoder, TAG_COMPOUND, "")?;
+    write_contents(&mut encoder)?;
+    encoder.write_all(&[TAG_END])?;
+
+    encoder.finish()?.flush()
+}
+
+fn write_tag_header(w: &mut dyn Write, tag_type: u8, name: &str) -> std::io::Result<()> {
+    w.write_all(&[tag_type])?;
+    write_string(w, name)
+}
+
+/// Writes a string with its length as a prefix.
+/// NBT uses modified UTF-8, which is the same as UTF-8 for the strings written here.
+fn write_string(w: &mut dyn Write, value: &str) -> std::io::Result<()> {
+    w.write_all(&(value.len() as u16).to_be_bytes())?;
+    w.write_all(value.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::Palette;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    /// An NBT value, with the names of compound entries in the order they were written.
+    #[derive(Debug, PartialEq)]
+    enum Tag {
+        Byte(u8),
+        Int(i32),
+        ByteArray(Vec<u8>),
+        String(String),
+        /// The element type and length of a list, which are always empty here.
+        List(u8, i32),
+        Compound(Vec<(String, Tag)>),
+    }
+
+    fn read_bytes<const N: usize>(r: &mut impl Read) -> [u8; N] {
+        let mut bytes = [0; N];
+        r.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read_string(r: &mut impl Read) -> String {
+        let mut bytes = vec![0; u16::from_be_bytes(read_bytes(r)) as usize];
+        r.read_exact(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn read_payload(r: &mut impl Read, tag_type: u8) -> Tag {
+        match tag_type {
+            TAG_BYTE => Tag::Byte(read_bytes::<1>(r)[0]),
+            TAG_INT => Tag::Int(i32::from_be_bytes(read_bytes(r))),
+            TAG_BYTE_ARRAY => {
+                let mut bytes = vec![0; i32::from_be_bytes(read_bytes(r)) as usize];
+                r.read_exact(&mut bytes).unwrap();
+                Tag::ByteArray(bytes)
+            }
+            TAG_STRING => Tag::String(read_string(r)),
+            TAG_LIST => {
+                let [element_type] = read_bytes(r);
+                let length = i32::from_be_bytes(read_bytes(r));
+                assert_eq!(length, 0, "only empty lists are written");
+                Tag::List(element_type, length)
+            }
+            TAG_COMPOUND => {
+                let mut entries = Vec::new();
+                loop {
+                    let [tag_type] = read_bytes(r);
+                    if tag_type == TAG_END {
+                        return Tag::Compound(entries);
+                    }
+                    let name = read_string(r);
+                    entries.push((name, read_payload(r, tag_type)));
+                }
+            }
+            _ => panic!("unexpected tag type {tag_type}"),
+        }
+    }
+
+    /// Reads a gzip-compressed NBT file and returns the entries of its root compound.
+    fn read_gzip_nbt(path: &Path) -> Vec<(String, Tag)> {
+        let mut r = GzDecoder::new(File::open(path).unwrap());
+        assert_eq!(read_bytes::<1>(&mut r)[0], TAG_COMPOUND);
+        assert_eq!(read_string(&mut r), "");
+        let Tag::Compound(entries) = read_payload(&mut r, TAG_COMPOUND) else {
+            unreachable!()
+        };
+        assert_eq!(r.read(&mut [0]).unwrap(), 0, "data after the root compound");
+        entries
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("map_export_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An image that is 2 pixels wider than a map and has the color ID of each pixel
+    /// set to 4 plus its row.
+    fn image() -> IndexedImage {
+        let (width, height) = (MAP_SIZE + 2, 3);
+        let indices = (0..width * height).map(|i| (4 + i / width) as u8).collect();
+        IndexedImage::new(width, height, indices, Arc::new(Palette::minecraft()))
+    }
+
+    #[test]
+    fn map_files() {
+        let dir = test_dir("map_files");
+        let exporter = MapExporter::new(5);
+        let paths = exporter.export(&image(), &dir).unwrap();
+        assert_eq!(paths, [dir.join("map_5.dat"), dir.join("map_6.dat")]);
+
+        for (path, width) in paths.iter().zip([MAP_SIZE, 2]) {
+            let mut root = read_gzip_nbt(path);
+            assert_eq!(
+                root.pop(),
+                Some(("DataVersion".to_string(), Tag::Int(DEFAULT_DATA_VERSION)))
+            );
+            let Some((name, Tag::Compound(mut data))) = root.pop() else {
+                panic!("expected the data compound");
+            };
+            assert_eq!(name, "data");
+            assert!(root.is_empty());
+
+            let Some((name, Tag::ByteArray(colors))) = data.pop() else {
+                panic!("expected the colors");
+            };
+            assert_eq!(name, "colors");
+            assert_eq!(colors.len(), 16384);
+            for (i, &color) in colors.iter().enumerate() {
+                let (x, y) = (i as u32 % MAP_SIZE, i as u32 / MAP_SIZE);
+                // Past the edge of the image the maps are transparent
+                let expected = if x < width && y < 3 { 4 + y as u8 } else { 0 };
+                assert_eq!(color, expected, "color at {x}, {y} of {}", path.display());
+            }
+
+            let expected = [
+                ("scale", Tag::Byte(0)),
+                ("dimension", Tag::String("minecraft:overworld".to_string())),
+                ("trackingPosition", Tag::Byte(0)),
+                ("unlimitedTracking", Tag::Byte(0)),
+                ("locked", Tag::Byte(1)),
+                ("xCenter", Tag::Int(0)),
+                ("zCenter", Tag::Int(0)),
+                ("banners", Tag::List(TAG_END, 0)),
+                ("frames", Tag::List(TAG_END, 0)),
+            ]
+            .map(|(name, tag)| (name.to_string(), tag));
+            assert_eq!(data, expected);
+        }
+
+        exporter.write_id_counts(&dir, 6).unwrap();
+        let expected = [
+            (
+                "data".to_string(),
+                Tag::Compound(vec![("map".to_string(), Tag::Int(6))]),
+            ),
+            ("DataVersion".to_string(), Tag::Int(DEFAULT_DATA_VERSION)),
+        ];
+        assert_eq!(read_gzip_nbt(&dir.join("idcounts.dat")), expected);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ids_past_u32_max() {
+        let dir = test_dir("ids_past_u32_max");
+        assert!(MapExporter::new(u32::MAX).export(&image(), &dir).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // The last map can have the largest ID
+        let paths = MapExporter::new(u32::MAX - 1)
+            .export(&image(), &dir)
+            .unwrap();
+        assert_eq!(paths.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
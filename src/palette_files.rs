@@ -0,0 +1,508 @@
+use crate::colors::Palette;
+use image::Rgb;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The maximum number of colors in a palette.
+const MAX_COLORS: usize = 256;
+
+/// The file formats that palettes can be loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// GIMP palette (`.gpl`).
+    Gpl,
+    /// Adobe Color Table (`.act`), 256 RGB triplets with an optional color count.
+    Act,
+    /// One `RRGGBB` or `AARRGGBB` hex color per line (`.hex`, `.txt`), as exported by Lospec
+    /// and Paint.NET. Lines starting with `;` are comments.
+    Hex,
+    /// A JSON array of `"#RRGGBB"` strings, `[r, g, b]` arrays or objects with either a `hex`
+    /// string or `r`, `g` and `b` numbers.
+    Json,
+    /// An image of color swatches, e.g. a 1-pixel tall strip. Every distinct color becomes an
+    /// entry, in the order they first appear.
+    Png,
+}
+
+impl PaletteFormat {
+    /// Guesses the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "act" => Some(PaletteFormat::Act),
+            "hex" | "txt" => Some(PaletteFormat::Hex),
+            "json" => Some(PaletteFormat::Json),
+            "png" => Some(PaletteFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// An error that occurred while loading a palette file.
+#[derive(Debug)]
+pub struct PaletteFileError {
+    pub path: PathBuf,
+    /// The 1-based line of the error, for text formats.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for PaletteFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for PaletteFileError {}
+
+/// An error without the path of the file, which is added by `load_palette_as`.
+struct ParseError {
+    line: Option<usize>,
+    message: String,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>) -> Self {
+        ParseError {
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    fn at(line: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            line: Some(line),
+            message: message.into(),
+        }
+    }
+}
+
+/// Loads a palette, guessing the format from the file extension.
+pub fn load_palette(path: impl AsRef<Path>) -> Result<Palette, PaletteFileError> {
+    let path = path.as_ref();
+    let format = PaletteFormat::from_path(path).ok_or_else(|| PaletteFileError {
+        path: path.to_path_buf(),
+        line: None,
+        message: "unknown palette format".to_string(),
+    })?;
+    load_palette_as(path, format)
+}
+
+/// Loads a palette in the given format.
+/// Unless the file contains a name, the palette is named after the file.
+pub fn load_palette_as(
+    path: impl AsRef<Path>,
+    format: PaletteFormat,
+) -> Result<Palette, PaletteFileError> {
+    let path = path.as_ref();
+    let default_name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let result = match format {
+        PaletteFormat::Png => parse_png(path).map(|colors| (None, colors)),
+        _ => match std::fs::read(path) {
+            Ok(bytes) => parse_bytes(&bytes, format),
+            Err(err) => Err(ParseError::new(err.to_string())),
+        },
+    };
+
+    let (name, colors) = result
+        .and_then(|(name, colors)| {
+            if colors.is_empty() {
+                Err(ParseError::new("the palette has no colors"))
+            } else if colors.len() > MAX_COLORS {
+                Err(ParseError::new(format!(
+                    "the palette has {} colors, but at most {MAX_COLORS} are supported",
+                    colors.len()
+                )))
+            } else {
+                Ok((name, colors))
+            }
+        })
+        .map_err(|err| PaletteFileError {
+            path: path.to_path_buf(),
+            line: err.line,
+            message: err.message,
+        })?;
+
+    Ok(Palette::new(name.unwrap_or(default_name), colors))
+}
+
+/// Parses the contents of a file, returning the name stored in the file (if any) and the colors.
+fn parse_bytes(
+    bytes: &[u8],
+    format: PaletteFormat,
+) -> Result<(Option<String>, Vec<Rgb<u8>>), ParseError> {
+    if format == PaletteFormat::Act {
+        return parse_act(bytes).map(|colors| (None, colors));
+    }
+
+    let text = std::str::from_utf8(bytes).map_err(|_| ParseError::new("the file isn't UTF-8"))?;
+    match format {
+        PaletteFormat::Gpl => parse_gpl(text),
+        PaletteFormat::Hex => parse_hex(text).map(|colors| (None, colors)),
+        PaletteFormat::Json => parse_json(text).map(|colors| (None, colors)),
+        PaletteFormat::Act | PaletteFormat::Png => unreachable!(),
+    }
+}
+
+fn parse_gpl(text: &str) -> Result<(Option<String>, Vec<Rgb<u8>>), ParseError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()));
+    match lines.next() {
+        Some((_, "GIMP Palette")) => {}
+        _ => return Err(ParseError::at(1, "expected the header `GIMP Palette`")),
+    }
+
+    let mut name = None;
+    let mut colors = Vec::new();
+    for (line_number, line) in lines {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("Name:") {
+            name = Some(value.trim().to_string());
+            continue;
+        }
+        if line.starts_with("Columns:") {
+            continue;
+        }
+
+        // Each color is three numbers followed by an optional name
+        let mut channels = [0; 3];
+        let mut fields = line.split_whitespace();
+        for channel in channels.iter_mut() {
+            let field = fields.next().ok_or_else(|| {
+                ParseError::at(
+                    line_number,
+                    "expected three numbers for red, green and blue",
+                )
+            })?;
+            *channel = field.parse().map_err(|_| {
+                ParseError::at(
+                    line_number,
+                    format!("`{field}` isn't a number from 0 to 255"),
+                )
+            })?;
+        }
+        colors.push(Rgb(channels));
+    }
+
+    Ok((name, colors))
+}
+
+fn parse_act(bytes: &[u8]) -> Result<Vec<Rgb<u8>>, ParseError> {
+    let mut colors: Vec<Rgb<u8>> = match bytes.len() {
+        768 | 772 => bytes[..768]
+            .chunks_exact(3)
+            .map(|rgb| Rgb([rgb[0], rgb[1], rgb[2]]))
+            .collect(),
+        len => {
+            return Err(ParseError::new(format!(
+                "expected 768 or 772 bytes, but the file has {len}"
+            )))
+        }
+    };
+
+    // The optional trailer stores the number of colors that are used
+    if bytes.len() == 772 {
+        let count = u16::from_be_bytes([bytes[768], bytes[769]]) as usize;
+        if count > 0 && count <= MAX_COLORS {
+            colors.truncate(count);
+        }
+    }
+
+    Ok(colors)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<Rgb<u8>>, ParseError> {
+    let mut colors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let color = parse_hex_color(line)
+            .ok_or_else(|| ParseError::at(i + 1, format!("`{line}` isn't a hex color")))?;
+        colors.push(color);
+    }
+    Ok(colors)
+}
+
+/// Parses `RRGGBB` or `AARRGGBB` with an optional `#`. The alpha channel is ignored.
+fn parse_hex_color(value: &str) -> Option<Rgb<u8>> {
+    let digits = value.strip_prefix('#').unwrap_or(value);
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let rgb = match digits.len() {
+        6 => digits,
+        8 => &digits[2..],
+        _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(&rgb[i * 2..i * 2 + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(1)?, channel(2)?]))
+}
+
+fn parse_png(path: &Path) -> Result<Vec<Rgb<u8>>, ParseError> {
+    let image = image::open(path)
+        .map_err(|err| ParseError::new(err.to_string()))?
+        .to_rgb8();
+
+    let mut colors = Vec::new();
+    for pixel in image.pixels() {
+        if !colors.contains(pixel) {
+            colors.push(*pixel);
+            if colors.len() > MAX_COLORS {
+                return Err(ParseError::new(format!(
+                    "the image has more than {MAX_COLORS} colors, so it isn't a palette"
+                )));
+            }
+        }
+    }
+    Ok(colors)
+}
+
+fn parse_json(text: &str) -> Result<Vec<Rgb<u8>>, ParseError> {
+    let colors: Vec<JsonColor> = serde_json::from_str(text).map_err(|err| {
+        // serde_json adds the position to the message, but the line is reported separately
+        let message = err.to_string();
+        let suffix = format!(" at line {} column {}", err.line(), err.column());
+        let message = message.strip_suffix(&suffix).unwrap_or(&message);
+        ParseError::at(err.line(), message)
+    })?;
+    Ok(colors.into_iter().map(|JsonColor(color)| color).collect())
+}
+
+/// A color in a JSON palette, see `PaletteFormat::Json` for the accepted values.
+struct JsonColor(Rgb<u8>);
+
+impl<'de> Deserialize<'de> for JsonColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonColorVisitor)
+    }
+}
+
+struct JsonColorVisitor;
+
+impl<'de> Visitor<'de> for JsonColorVisitor {
+    type Value = JsonColor;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex string, an [r, g, b] array or an object")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<JsonColor, E> {
+        parse_hex_color(value)
+            .map(JsonColor)
+            .ok_or_else(|| E::custom(format!("`{value}` isn't a hex color")))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsonColor, A::Error> {
+        let mut channels = [0; 3];
+        for channel in channels.iter_mut() {
+            let JsonChannel(value) = seq.next_element()?.ok_or_else(|| {
+                de::Error::custom("expected three numbers for red, green and blue")
+            })?;
+            *channel = value;
+        }
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::custom(
+                "expected three numbers for red, green and blue",
+            ));
+        }
+        Ok(JsonColor(Rgb(channels)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsonColor, A::Error> {
+        let mut hex = None;
+        let mut channels = [None; 3];
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "hex" => hex = Some(map.next_value::<JsonColor>()?),
+                "r" => channels[0] = Some(map.next_value::<JsonChannel>()?.0),
+                "g" => channels[1] = Some(map.next_value::<JsonChannel>()?.0),
+                "b" => channels[2] = Some(map.next_value::<JsonChannel>()?.0),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        match (hex, channels) {
+            (Some(color), _) => Ok(color),
+            (None, [Some(r), Some(g), Some(b)]) => Ok(JsonColor(Rgb([r, g, b]))),
+            _ => Err(de::Error::custom(
+                "expected a `hex` string or `r`, `g` and `b` numbers",
+            )),
+        }
+    }
+}
+
+/// A channel of a color in a JSON palette: a whole number from 0 to 255.
+struct JsonChannel(u8);
+
+impl<'de> Deserialize<'de> for JsonChannel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Whole numbers with a fraction, like `255.0`, are accepted too
+        let value = f64::deserialize(deserializer)?;
+        if value.fract() == 0.0 && (0.0..=255.0).contains(&value) {
+            Ok(JsonChannel(value as u8))
+        } else {
+            Err(de::Error::custom(format!(
+                "`{value}` isn't a number from 0 to 255"
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, format: PaletteFormat) -> Result<Vec<Rgb<u8>>, ParseError> {
+        parse_bytes(text.as_bytes(), format).map(|(_, colors)| colors)
+    }
+
+    fn error_line(result: Result<Vec<Rgb<u8>>, ParseError>) -> Option<usize> {
+        match result {
+            Ok(colors) => panic!("expected an error, but parsed {colors:?}"),
+            Err(err) => err.line,
+        }
+    }
+
+    #[test]
+    fn gpl() {
+        let text = "GIMP Palette\nName: Test\nColumns: 2\n# comment\n255 0 0 Red\n  0 128 255\n";
+        let (name, colors) = parse_bytes(text.as_bytes(), PaletteFormat::Gpl)
+            .ok()
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("Test"));
+        assert_eq!(colors, [Rgb([255, 0, 0]), Rgb([0, 128, 255])]);
+
+        assert_eq!(error_line(parse("255 0 0\n", PaletteFormat::Gpl)), Some(1));
+        let text = "GIMP Palette\n1 2 3\n1 2\n";
+        assert_eq!(error_line(parse(text, PaletteFormat::Gpl)), Some(3));
+        let text = "GIMP Palette\n1 2 256\n";
+        assert_eq!(error_line(parse(text, PaletteFormat::Gpl)), Some(2));
+    }
+
+    #[test]
+    fn act() {
+        let mut bytes = vec![0; 772];
+        bytes[..6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        bytes[768..770].copy_from_slice(&2_u16.to_be_bytes());
+        let colors = parse_act(&bytes).ok().unwrap();
+        assert_eq!(colors, [Rgb([1, 2, 3]), Rgb([4, 5, 6])]);
+        assert_eq!(parse_act(&bytes[..768]).ok().unwrap().len(), 256);
+
+        assert!(parse_act(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn hex() {
+        let text = "; comment\nff0000\n#00FF00\nff0000ff\n";
+        let colors = parse(text, PaletteFormat::Hex).ok().unwrap();
+        assert_eq!(
+            colors,
+            [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])]
+        );
+
+        assert_eq!(
+            error_line(parse("ff0000\n\nf00\n", PaletteFormat::Hex)),
+            Some(3)
+        );
+        assert_eq!(error_line(parse("gg0000\n", PaletteFormat::Hex)), Some(1));
+    }
+
+    #[test]
+    fn json() {
+        let text = r##"[
+            "#ff0000",
+            [0, 128, 255.0],
+            {"hex": "00ff00", "name": "green"},
+            {"r": 1, "g": 2, "b": 3}
+        ]"##;
+        let colors = parse(text, PaletteFormat::Json).ok().unwrap();
+        assert_eq!(
+            colors,
+            [
+                Rgb([255, 0, 0]),
+                Rgb([0, 128, 255]),
+                Rgb([0, 255, 0]),
+                Rgb([1, 2, 3])
+            ]
+        );
+
+        // Escapes are decoded before the hex color is parsed
+        let colors = parse(r#"["\u0023ff0000"]"#, PaletteFormat::Json)
+            .ok()
+            .unwrap();
+        assert_eq!(colors, [Rgb([255, 0, 0])]);
+
+        assert_eq!(
+            error_line(parse("[\n\"#ff0000\",\n\"nope\"\n]", PaletteFormat::Json)),
+            Some(3)
+        );
+        assert_eq!(
+            error_line(parse("[\n[1, 2, 300]\n]", PaletteFormat::Json)),
+            Some(2)
+        );
+        assert_eq!(
+            error_line(parse("[\n[1, 2]\n]", PaletteFormat::Json)),
+            Some(2)
+        );
+        assert_eq!(
+            error_line(parse("[\n{\"r\": 1}\n]", PaletteFormat::Json)),
+            Some(2)
+        );
+        assert_eq!(error_line(parse("{}", PaletteFormat::Json)), Some(1));
+        assert_eq!(
+            error_line(parse("[\"#ff0000\",\n", PaletteFormat::Json)),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn deeply_nested_json() {
+        let text = "[".repeat(1_000_000);
+        assert_eq!(error_line(parse(&text, PaletteFormat::Json)), Some(1));
+    }
+
+    #[test]
+    fn png() {
+        // The process ID keeps test runs that happen at the same time from sharing the file
+        let id = std::process::id();
+        let path = std::env::temp_dir().join(format!("palette_files_test_swatches_{id}.png"));
+        let colors = [
+            Rgb([255, 0, 0]),
+            Rgb([0, 255, 0]),
+            Rgb([255, 0, 0]),
+            Rgb([0, 0, 255]),
+        ];
+        image::RgbImage::from_fn(4, 1, |x, _| colors[x as usize])
+            .save(&path)
+            .unwrap();
+        let parsed = parse_png(&path).ok();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            parsed.unwrap(),
+            [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])]
+        );
+
+        let path = std::env::temp_dir().join(format!("palette_files_test_missing_{id}.png"));
+        assert!(parse_png(&path).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+use crate::colors::Palette;
+use image::{Rgb, RgbImage};
+use std::collections::HashMap;
+
+/// The algorithm that picks the initial palette colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizeMethod {
+    /// Repeatedly splits the box of colors with the widest range at its median.
+    MedianCut,
+    /// Builds an octree of the colors and merges the leaves with the fewest pixels.
+    Octree,
+    /// Picks random colors with k-means++, so the result depends on the seed.
+    KMeans,
+}
+
+impl QuantizeMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            QuantizeMethod::MedianCut => "median cut",
+            QuantizeMethod::Octree => "octree",
+            QuantizeMethod::KMeans => "k-means",
+        }
+    }
+}
+
+/// Generates a palette with the best colors for an image.
+pub struct PaletteGenerator {
+    /// The maximum number of colors, up to 256.
+    /// The palette has fewer colors if the image doesn't have enough distinct colors.
+    pub colors: usize,
+    pub method: QuantizeMethod,
+    /// The maximum number of k-means iterations that refine the colors picked by the method.
+    pub refine_iterations: usize,
+    /// The seed for the random choices of k-means, so the same seed gives the same palette.
+    pub seed: u64,
+}
+
+impl PaletteGenerator {
+    pub fn new(colors: usize) -> Self {
+        PaletteGenerator {
+            colors,
+            method: QuantizeMethod::MedianCut,
+            refine_iterations: 8,
+            seed: 0,
+        }
+    }
+
+    /// Returns a palette for the image, which can be used to build a `ColorTree`.
+    ///
+    /// # Panics
+    /// Panics if the number of colors isn't between 1 and 256, or if the image has no pixels to
+    /// pick colors from.
+    pub fn generate(&self, image: &RgbImage) -> Palette {
+        assert!(
+            (1..=256).contains(&self.colors),
+            "a palette must have between 1 and 256 colors"
+        );
+
+        let histogram = histogram(image);
+        assert!(
+            !histogram.is_empty(),
+            "can't generate a palette for an image without pixels"
+        );
+        let mut rng = SplitMix64(self.seed);
+
+        let mut centers = match self.method {
+            QuantizeMethod::MedianCut => median_cut(&histogram, self.colors),
+            QuantizeMethod::Octree => octree(&histogram, self.colors),
+            QuantizeMethod::KMeans => kmeans_plus_plus(&histogram, self.colors, &mut rng),
+        };
+        kmeans(&histogram, &mut centers, self.refine_iterations, &mut rng);
+
+        // Centers can round to the same color, which shouldn't be in the palette twice
+        let mut colors: Vec<Rgb<u8>> = Vec::with_capacity(centers.len());
+        for center in centers {
+            let color = Rgb(center.map(|channel| channel.round().clamp(0.0, 255.0) as u8));
+            if !colors.contains(&color) {
+                colors.push(color);
+            }
+        }
+
+        let name = format!("{} colors ({})", colors.len(), self.method.name());
+        Palette::new(name, colors)
+    }
+}
+
+/// A distinct color of the image and the number of pixels with that color.
+#[derive(Debug, Clone, Copy)]
+struct HistogramEntry {
+    color: [u8; 3],
+    count: u32,
+}
+
+impl HistogramEntry {
+    fn point(&self) -> [f32; 3] {
+        self.color.map(|channel| channel as f32)
+    }
+}
+
+fn histogram(image: &RgbImage) -> Vec<HistogramEntry> {
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in image.pixels() {
+        *counts.entry(pixel.0).or_insert(0) += 1;
+    }
+
+    // Sort so the result doesn't depend on the iteration order of the map
+    let mut entries: Vec<HistogramEntry> = counts
+        .into_iter()
+        .map(|(color, count)| HistogramEntry { color, count })
+        .collect();
+    entries.sort_unstable_by_key(|entry| entry.color);
+    entries
+}
+
+/// Returns the mean color of the entries, weighted by their pixel count.
+fn mean(entries: &[HistogramEntry]) -> [f32; 3] {
+    let mut sum = [0.0_f64; 3];
+    let mut total = 0.0_f64;
+    for entry in entries {
+        for (sum, channel) in sum.iter_mut().zip(entry.color) {
+            *sum += channel as f64 * entry.count as f64;
+        }
+        total += entry.count as f64;
+    }
+    sum.map(|sum| (sum / total) as f32)
+}
+
+fn squared_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+fn median_cut(histogram: &[HistogramEntry], colors: usize) -> Vec<[f32; 3]> {
+    let mut boxes: Vec<Vec<HistogramEntry>> = vec![histogram.to_vec()];
+
+    while boxes.len() < colors {
+        // Find the box and channel with the widest range of values
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, entries)| entries.len() > 1)
+            .flat_map(|(i, entries)| {
+                (0..3).map(move |channel| {
+                    let values = entries.iter().map(|entry| entry.color[channel]);
+                    let range = values.clone().max().unwrap() - values.min().unwrap();
+                    (range, i, channel)
+                })
+            })
+            .max_by_key(|&(range, _, _)| range);
+
+        let Some((_, i, channel)) = widest else {
+            // Every box has a single color
+            break;
+        };
+
+        // Split at the median pixel, keeping at least one entry on each side
+        let mut entries = boxes.swap_remove(i);
+        entries.sort_unstable_by_key(|entry| (entry.color[channel], entry.color));
+        let total: u64 = entries.iter().map(|entry| entry.count as u64).sum();
+        let mut seen = 0;
+        let mut split = 1;
+        for (j, entry) in entries.iter().enumerate() {
+            seen += entry.count as u64;
+            if seen * 2 >= total {
+                split = (j + 1).clamp(1, entries.len() - 1);
+                break;
+            }
+        }
+
+        let upper = entries.split_off(split);
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|entries| mean(entries)).collect()
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    sum: [u64; 3],
+    count: u64,
+    is_leaf: bool,
+}
+
+fn octree(histogram: &[HistogramEntry], colors: usize) -> Vec<[f32; 3]> {
+    const DEPTH: usize = 8;
+
+    // Nodes are stored in an arena and `levels` lists the inner nodes at each depth
+    let mut nodes = vec![OctreeNode::default()];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); DEPTH];
+    let mut leaves = 0;
+
+    for entry in histogram {
+        let mut node = 0;
+        for depth in 0..DEPTH {
+            let bit = 7 - depth;
+            let octant = (0..3).fold(0, |octant, channel| {
+                octant << 1 | ((entry.color[channel] >> bit) & 1) as usize
+            });
+
+            node = match nodes[node].children[octant] {
+                Some(child) => child,
+                None => {
+                    let child = nodes.len();
+                    let is_leaf = depth == DEPTH - 1;
+                    nodes.push(OctreeNode {
+                        is_leaf,
+                        ..Default::default()
+                    });
+                    nodes[node].children[octant] = Some(child);
+                    if is_leaf {
+                        leaves += 1;
+                    } else {
+                        levels[depth + 1].push(child);
+                    }
+                    child
+                }
+            };
+        }
+
+        let leaf = &mut nodes[node];
+        for (sum, channel) in leaf.sum.iter_mut().zip(entry.color) {
+            *sum += channel as u64 * entry.count as u64;
+        }
+        leaf.count += entry.count as u64;
+    }
+    levels[0].push(0);
+
+    // Merge the children of the deepest inner nodes with the fewest pixels
+    // until few enough leaves remain
+    for depth in (0..DEPTH).rev() {
+        for &node in &levels[depth] {
+            let mut sum = [0; 3];
+            let mut count = 0;
+            for child in nodes[node].children.into_iter().flatten() {
+                for (sum, child_sum) in sum.iter_mut().zip(nodes[child].sum) {
+                    *sum += child_sum;
+                }
+                count += nodes[child].count;
+            }
+            nodes[node].sum = sum;
+            nodes[node].count = count;
+        }
+
+        let mut reducible = levels[depth].clone();
+        reducible.sort_by_key(|&node| (nodes[node].count, node));
+        for node in reducible {
+            if leaves <= colors {
+                break;
+            }
+            let children = nodes[node].children.into_iter().flatten().count();
+            nodes[node].children = [None; 8];
+            nodes[node].is_leaf = true;
+            leaves -= children - 1;
+        }
+
+        if leaves <= colors {
+            break;
+        }
+    }
+
+    // Collect the leaves that are still in the tree, i.e. none of their ancestors were merged
+    let mut centers = Vec::new();
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.is_leaf {
+            centers.push(node.sum.map(|sum| (sum as f64 / node.count as f64) as f32));
+        } else {
+            stack.extend(node.children.into_iter().flatten());
+        }
+    }
+    centers
+}
+
+fn kmeans_plus_plus(
+    histogram: &[HistogramEntry],
+    colors: usize,
+    rng: &mut SplitMix64,
+) -> Vec<[f32; 3]> {
+    let weighted_pick = |weights: &[f64], rng: &mut SplitMix64| {
+        let total: f64 = weights.iter().sum();
+        let mut target = rng.next_f64() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            target -= weight;
+            if target < 0.0 {
+                return i;
+            }
+        }
+        weights.len() - 1
+    };
+
+    // The first center is picked by pixel count, the others by their distance to the closest center
+    let counts: Vec<f64> = histogram.iter().map(|entry| entry.count as f64).collect();
+    let mut centers = vec![histogram[weighted_pick(&counts, rng)].point()];
+    let mut distances: Vec<f64> = histogram
+        .iter()
+        .map(|entry| squared_distance(entry.point(), centers[0]) as f64 * entry.count as f64)
+        .collect();
+
+    while centers.len() < colors.min(histogram.len()) {
+        if distances.iter().all(|&distance| distance == 0.0) {
+            break;
+        }
+
+        let center = histogram[weighted_pick(&distances, rng)].point();
+        for (distance, entry) in distances.iter_mut().zip(histogram) {
+            let new = squared_distance(entry.point(), center) as f64 * entry.count as f64;
+            *distance = distance.min(new);
+        }
+        centers.push(center);
+    }
+
+    centers
+}
+
+/// Moves the centers to the mean of the colors closest to them (Lloyd's algorithm).
+fn kmeans(
+    histogram: &[HistogramEntry],
+    centers: &mut [[f32; 3]],
+    iterations: usize,
+    rng: &mut SplitMix64,
+) {
+    let mut assignments = vec![usize::MAX; histogram.len()];
+
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (assignment, entry) in assignments.iter_mut().zip(histogram) {
+            let point = entry.point();
+            let closest = (0..centers.len())
+                .min_by(|&a, &b| {
+                    squared_distance(point, centers[a])
+                        .total_cmp(&squared_distance(point, centers[b]))
+                })
+                .unwrap();
+            if *assignment != closest {
+                *assignment = closest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![[0.0_f64; 3]; centers.len()];
+        let mut totals = vec![0.0_f64; centers.len()];
+        for (&assignment, entry) in assignments.iter().zip(histogram) {
+            for (sum, channel) in sums[assignment].iter_mut().zip(entry.color) {
+                *sum += channel as f64 * entry.count as f64;
+            }
+            totals[assignment] += entry.count as f64;
+        }
+
+        for (i, center) in centers.iter_mut().enumerate() {
+            if totals[i] > 0.0 {
+                *center = sums[i].map(|sum| (sum / totals[i]) as f32);
+            } else {
+                // Move empty clusters to a random color so they can be useful again
+                let entry = histogram[rng.next_u64() as usize % histogram.len()];
+                *center = entry.point();
+            }
+        }
+    }
+}
+
+/// A small deterministic random number generator, so palettes are reproducible from a seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [QuantizeMethod; 3] = [
+        QuantizeMethod::MedianCut,
+        QuantizeMethod::Octree,
+        QuantizeMethod::KMeans,
+    ];
+
+    fn generator(colors: usize, method: QuantizeMethod) -> PaletteGenerator {
+        PaletteGenerator {
+            method,
+            ..PaletteGenerator::new(colors)
+        }
+    }
+
+    #[test]
+    fn few_distinct_colors() {
+        let colors = [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])];
+        let image = RgbImage::from_fn(6, 4, |x, y| colors[((x + y) % 3) as usize]);
+        for method in METHODS {
+            let palette = generator(16, method).generate(&image);
+            assert_eq!(palette.len(), 3, "{method:?}");
+            for color in colors {
+                assert!(
+                    palette.colors().iter().any(|entry| entry.rgb == color),
+                    "{method:?} is missing {color:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_image_panics() {
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let image = RgbImage::new(width, height);
+            for method in METHODS {
+                let result = std::panic::catch_unwind(|| generator(4, method).generate(&image));
+                assert!(result.is_err(), "{method:?} on a {width}x{height} image");
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Each test crate only uses some of these
+#![allow(dead_code)]
+
+use floyd_steinberg_parallel_test::colors::{ColorSearch, ColorTree, Palette};
+use floyd_steinberg_parallel_test::convert::{Converter, DiffusionOptions, ParallelConverter};
+use floyd_steinberg_parallel_test::convert_channels::ChannelConverter;
+use floyd_steinberg_parallel_test::convert_diagonal::DiagonalConverter;
+use floyd_steinberg_parallel_test::convert_dot::DotDiffusionConverter;
+use floyd_steinberg_parallel_test::convert_mutex::MutexConverter;
+use floyd_steinberg_parallel_test::convert_nearest::NearestConverter;
+use floyd_steinberg_parallel_test::convert_ordered::OrderedConverter;
+use floyd_steinberg_parallel_test::convert_pool::PoolConverter;
+use floyd_steinberg_parallel_test::convert_wavefront::WavefrontConverter;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::Arc;
+
+/// A converter and the name that failed assertions report it by.
+pub type NamedConverter = (&'static str, Box<dyn Converter>);
+
+pub fn tree() -> Arc<dyn ColorSearch> {
+    Arc::new(ColorTree::new(Arc::new(Palette::minecraft())))
+}
+
+pub fn thread_pool(threads: usize) -> Arc<ThreadPool> {
+    Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap(),
+    )
+}
+
+/// Returns the parallel error-diffusion converters with the given options,
+/// running on the given thread pool.
+pub fn diffusion_converters(
+    search: &Arc<dyn ColorSearch>,
+    options: &DiffusionOptions,
+    pool: &Arc<ThreadPool>,
+) -> Vec<NamedConverter> {
+    vec![
+        (
+            "mutex",
+            Box::new(
+                MutexConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone()),
+            ),
+        ),
+        (
+            "channels",
+            Box::new(
+                ChannelConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone()),
+            ),
+        ),
+        (
+            "wavefront",
+            Box::new(
+                WavefrontConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone()),
+            ),
+        ),
+        (
+            "pool",
+            Box::new(
+                PoolConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone()),
+            ),
+        ),
+        (
+            "pool-1",
+            Box::new(
+                PoolConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone())
+                    .with_workers(1),
+            ),
+        ),
+        (
+            "pool-3",
+            Box::new(
+                PoolConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone())
+                    .with_workers(3),
+            ),
+        ),
+        (
+            "diagonal",
+            Box::new(
+                DiagonalConverter::new(search.clone())
+                    .with_options(options.clone())
+                    .with_thread_pool(pool.clone()),
+            ),
+        ),
+    ]
+}
+
+/// Returns every parallel converter with its default options, running on the given thread pool.
+pub fn parallel_converters(
+    search: &Arc<dyn ColorSearch>,
+    pool: &Arc<ThreadPool>,
+) -> Vec<NamedConverter> {
+    let mut converters = diffusion_converters(search, &DiffusionOptions::default(), pool);
+    converters.extend([
+        (
+            "dot",
+            Box::new(DotDiffusionConverter::new(search.clone()).with_thread_pool(pool.clone()))
+                as Box<dyn Converter>,
+        ),
+        (
+            "nearest",
+            Box::new(NearestConverter::new(search.clone()).with_thread_pool(pool.clone())),
+        ),
+        (
+            "ordered",
+            Box::new(OrderedConverter::new(search.clone()).with_thread_pool(pool.clone())),
+        ),
+    ]);
+    converters
+}
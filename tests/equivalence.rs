@@ -0,0 +1,214 @@
+mod common;
+
+use common::{diffusion_converters, thread_pool, tree};
+use floyd_steinberg_parallel_test::convert::{
+    ColorSpace, Converter, DiffusionOptions, IndexedImage, Kernel,
+};
+use floyd_steinberg_parallel_test::convert_pool::PoolConverter;
+use floyd_steinberg_parallel_test::convert_single_threaded::SingleThreadedConverter;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// The number of threads that the parallel converters run on, so that rows really are
+/// converted at the same time even on a machine with one core.
+const THREADS: usize = 4;
+
+fn kernels() -> [Kernel; 9] {
+    [
+        Kernel::floyd_steinberg(),
+        Kernel::jarvis_judice_ninke(),
+        Kernel::stucki(),
+        Kernel::burkes(),
+        Kernel::sierra(),
+        Kernel::two_row_sierra(),
+        Kernel::sierra_lite(),
+        Kernel::atkinson(),
+        Kernel::false_floyd_steinberg(),
+    ]
+}
+
+/// Asserts that every parallel converter gives the same indices as `SingleThreadedConverter`.
+fn assert_equivalent(image: &RgbImage, options: &DiffusionOptions) {
+    assert_equivalent_on(image, options, THREADS);
+}
+
+/// Asserts that every parallel converter gives the same indices as `SingleThreadedConverter`
+/// when it runs on a pool with the given number of threads.
+fn assert_equivalent_on(image: &RgbImage, options: &DiffusionOptions, threads: usize) {
+    let tree = tree();
+    let reference = SingleThreadedConverter::new(tree.clone())
+        .with_options(options.clone())
+        .convert(image.clone());
+
+    let pool = thread_pool(threads);
+    for (name, converter) in diffusion_converters(&tree, options, &pool) {
+        let result = converter.convert(image.clone());
+        assert_eq!(result.dimensions(), reference.dimensions());
+        assert!(
+            result.indices() == reference.indices(),
+            "{name} differs from the single-threaded converter on a {}x{} image \
+             with {threads} threads and {options:?}",
+            image.width(),
+            image.height(),
+        );
+    }
+}
+
+/// A smooth gradient, where the error builds up over long distances.
+fn gradient(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        Rgb([
+            (x * 255 / width.max(2).saturating_sub(1)) as u8,
+            (y * 255 / height.max(2).saturating_sub(1)) as u8,
+            ((x + y) * 4) as u8,
+        ])
+    })
+}
+
+/// Random colors from a fixed seed, which give large errors in every direction.
+fn noise(width: u32, height: u32) -> RgbImage {
+    let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+    RgbImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let [r, g, b, ..] = state.to_le_bytes();
+        Rgb([r, g, b])
+    })
+}
+
+/// A part of a bundled test image, small enough to convert many times.
+fn bundled(file: &str) -> RgbImage {
+    let path = format!("{}/test_images/{file}", env!("CARGO_MANIFEST_DIR"));
+    let image = image::open(path).unwrap().to_rgb8();
+    let (width, height) = (96, 72);
+    let x = (image.width() - width) / 2;
+    let y = (image.height() - height) / 2;
+    image::imageops::crop_imm(&image, x, y, width, height).to_image()
+}
+
+#[test]
+fn bundled_images() {
+    for file in ["700x980.jpg", "1920x1000.png"] {
+        let image = bundled(file);
+        for serpentine in [false, true] {
+            let options = DiffusionOptions {
+                serpentine,
+                ..Default::default()
+            };
+            assert_equivalent(&image, &options);
+        }
+    }
+}
+
+#[test]
+fn every_kernel() {
+    let image = gradient(48, 32);
+    for kernel in kernels() {
+        for serpentine in [false, true] {
+            let options = DiffusionOptions {
+                kernel: kernel.clone(),
+                serpentine,
+                ..Default::default()
+            };
+            assert_equivalent(&image, &options);
+        }
+    }
+}
+
+#[test]
+fn every_kernel_on_noise() {
+    let image = noise(40, 24);
+    for kernel in kernels() {
+        let options = DiffusionOptions {
+            kernel,
+            color_space: ColorSpace::Linear,
+            ..Default::default()
+        };
+        assert_equivalent(&image, &options);
+    }
+}
+
+#[test]
+fn strength_and_error_limit() {
+    let image = gradient(48, 32);
+    for (strength, error_limit) in [
+        (0.0, None),
+        (0.6, None),
+        (1.0, Some(0.05)),
+        (0.8, Some(0.2)),
+    ] {
+        for color_space in [ColorSpace::Srgb, ColorSpace::Linear] {
+            let options = DiffusionOptions {
+                color_space,
+                kernel: Kernel::stucki(),
+                strength,
+                error_limit,
+                ..Default::default()
+            };
+            assert_equivalent(&image, &options);
+        }
+    }
+}
+
+#[test]
+fn small_and_empty_images() {
+    let sizes = [
+        (0, 0),
+        (0, 3),
+        (3, 0),
+        (1, 1),
+        (1, 9),
+        (9, 1),
+        (2, 2),
+        (3, 17),
+        (17, 3),
+    ];
+    for (width, height) in sizes {
+        let image = noise(width, height);
+        for kernel in [Kernel::floyd_steinberg(), Kernel::jarvis_judice_ninke()] {
+            for serpentine in [false, true] {
+                let options = DiffusionOptions {
+                    kernel: kernel.clone(),
+                    serpentine,
+                    ..Default::default()
+                };
+                assert_equivalent(&image, &options);
+            }
+        }
+    }
+}
+
+#[test]
+fn thread_counts() {
+    let image = noise(32, 24);
+    for threads in [1, 2, 3] {
+        for kernel in [Kernel::floyd_steinberg(), Kernel::stucki()] {
+            let options = DiffusionOptions {
+                kernel,
+                ..Default::default()
+            };
+            assert_equivalent_on(&image, &options, threads);
+        }
+    }
+}
+
+#[test]
+fn pool_inside_busy_pool() {
+    // Every thread of the pool converts an image, so the workers of each conversion are queued
+    // behind the other conversions
+    let tree = tree();
+    let images: Vec<RgbImage> = (0..8).map(|i| noise(64, 64 + i)).collect();
+    let pool = thread_pool(THREADS);
+    let results: Vec<IndexedImage> = pool.install(|| {
+        images
+            .par_iter()
+            .map(|image| PoolConverter::new(tree.clone()).convert(image.clone()))
+            .collect()
+    });
+
+    let reference = SingleThreadedConverter::new(tree.clone());
+    for (image, result) in images.iter().zip(results) {
+        assert!(result.indices() == reference.convert(image.clone()).indices());
+    }
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{parallel_converters, thread_pool};
+use floyd_steinberg_parallel_test::colors::{ColorSearch, ColorTree, Palette, PaletteColor};
+use floyd_steinberg_parallel_test::convert::{Converter, ParallelConverter};
+use floyd_steinberg_parallel_test::convert_pool::PoolConverter;
+use floyd_steinberg_parallel_test::convert_wavefront::WavefrontConverter;
+use image::{Rgb, RgbImage};
+use std::sync::{Arc, Mutex};
+
+/// A `ColorTree` that records the number of threads of the pool that each search runs on.
+struct RecordingSearch {
+    tree: ColorTree,
+    threads: Mutex<Vec<usize>>,
+}
+
+impl RecordingSearch {
+    fn new() -> Arc<Self> {
+        Arc::new(RecordingSearch {
+            tree: ColorTree::new(Arc::new(Palette::minecraft())),
+            threads: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn take_threads(&self) -> Vec<usize> {
+        std::mem::take(&mut self.threads.lock().unwrap())
+    }
+}
+
+impl ColorSearch for RecordingSearch {
+    fn palette(&self) -> &Arc<Palette> {
+        self.tree.palette()
+    }
+
+    fn find_closest(&self, color: &Rgb<u8>) -> (PaletteColor, [i16; 3]) {
+        self.threads
+            .lock()
+            .unwrap()
+            .push(rayon::current_num_threads());
+        self.tree.find_closest(color)
+    }
+}
+
+fn image() -> RgbImage {
+    RgbImage::from_fn(24, 16, |x, y| Rgb([(x * 10) as u8, (y * 15) as u8, 128]))
+}
+
+#[test]
+fn converters_run_on_their_thread_pool() {
+    // Called from a pool with more threads, so the converters only see one thread
+    // if they really run on their own pool
+    let search = RecordingSearch::new();
+    let own_pool = thread_pool(1);
+    let dyn_search: Arc<dyn ColorSearch> = search.clone();
+    thread_pool(4).install(|| {
+        for (name, converter) in parallel_converters(&dyn_search, &own_pool) {
+            converter.convert(image());
+            let threads = search.take_threads();
+            assert!(!threads.is_empty(), "{name} didn't search for any colors");
+            assert!(
+                threads.iter().all(|&threads| threads == 1),
+                "{name} ran on a pool with {threads:?} threads instead of its own pool"
+            );
+        }
+    });
+}
+
+#[test]
+fn with_threads_builds_a_pool() {
+    let search = RecordingSearch::new();
+    thread_pool(4).install(|| {
+        PoolConverter::new(search.clone())
+            .with_threads(2)
+            .convert(image());
+    });
+    let threads = search.take_threads();
+    assert!(!threads.is_empty());
+    assert!(threads.iter().all(|&threads| threads == 2), "{threads:?}");
+}
+
+#[test]
+fn current_pool_by_default() {
+    let search = RecordingSearch::new();
+    thread_pool(3).install(|| {
+        WavefrontConverter::new(search.clone()).convert(image());
+    });
+    let threads = search.take_threads();
+    assert!(!threads.is_empty());
+    assert!(threads.iter().all(|&threads| threads == 3), "{threads:?}");
+}